@@ -0,0 +1,196 @@
+/*
+ *	MetaCall Library by Parra Studios
+ *	A library for providing a foreign function interface calls.
+ *
+ *	Copyright (C) 2016 - 2021 Vicente Eduardo Ferrer Garcia <vic798@gmail.com>
+ *
+ *	Licensed under the Apache License, Version 2.0 (the "License");
+ *	you may not use this file except in compliance with the License.
+ *	You may obtain a copy of the License at
+ *
+ *		http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *	Unless required by applicable law or agreed to in writing, software
+ *	distributed under the License is distributed on an "AS IS" BASIS,
+ *	WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *	See the License for the specific language governing permissions and
+ *	limitations under the License.
+ *
+ */
+
+//! Introspection of the loaded scripts, metacall_inspect returns a JSON document
+//! of the form `{ tag: [ { name, scope: { funcs: [ { name, signature, async } ] } } ] }`
+//! which is deserialized with the MetaCall serial and converted into these structs
+
+use crate::{deserialize, entry, Allocator, Any, Error, ErrorKind, MetacallValueId};
+use std::os::raw::{c_int, c_void};
+
+/// Everything loaded in MetaCall, grouped by module
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inspection {
+    pub modules: Vec<InspectModule>,
+}
+
+/// Module (script or package) loaded by a loader
+#[derive(Debug, Clone, PartialEq)]
+pub struct InspectModule {
+    /// Tag of the loader which loaded the module (i.e py, node, rb...)
+    pub tag: String,
+    pub name: String,
+    pub functions: Vec<InspectFunction>,
+}
+
+/// Function exported by a module
+#[derive(Debug, Clone, PartialEq)]
+pub struct InspectFunction {
+    pub name: String,
+    pub args: Vec<InspectParameter>,
+    /// Return type, None if it is not known (i.e dynamically typed languages)
+    pub ret: Option<MetacallValueId>,
+    pub is_async: bool,
+}
+
+/// Parameter of a function
+#[derive(Debug, Clone, PartialEq)]
+pub struct InspectParameter {
+    pub name: String,
+    /// Type of the parameter, None if it is not known (i.e dynamically typed languages)
+    pub type_id: Option<MetacallValueId>,
+}
+
+impl Inspection {
+    /// Iterate through all the functions of all the modules
+    pub fn functions(&self) -> impl Iterator<Item = &InspectFunction> {
+        self.modules.iter().flat_map(|m| m.functions.iter())
+    }
+}
+
+/// Inspect all the loaded modules and the functions they export
+pub fn inspect() -> Result<Inspection, Error> {
+    entry(|| {
+        let allocator = Allocator::new()?;
+        let json = unsafe {
+            let mut size: usize = 0;
+            let st = crate::metacall_inspect(&mut size, allocator.0);
+            if st.is_null() {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    "MetaCall failed to inspect the loaded modules",
+                ));
+            }
+            let json = std::ffi::CStr::from_ptr(st).to_string_lossy().into_owned();
+            crate::metacall_allocator_free(allocator.0, st as *mut c_void);
+            json
+        };
+        parse_inspection(&deserialize(&json)?)
+    })
+}
+
+/// Names of the parameters of a loaded function (i.e for calling it with keyword arguments),
+/// it is empty when the loader does not provide them; if more than one module exports a
+/// function with the same name, the first one found is used
+pub fn function_param_names(func: &str) -> Result<Vec<String>, Error> {
+    entry(|| {
+        let inspection = inspect()?;
+        let function = inspection
+            .functions()
+            .find(|f| f.name == func)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::FunctionNotFound,
+                    format!("Function '{}' not found", func),
+                )
+            })?;
+        if function.args.iter().any(|arg| arg.name.is_empty()) {
+            return Ok(Vec::new());
+        }
+        Ok(function.args.iter().map(|arg| arg.name.clone()).collect())
+    })
+}
+
+fn get<'a>(value: &'a Any, key: &str) -> Option<&'a Any> {
+    match value {
+        Any::Map(entries) => entries.iter().find_map(|(k, v)| match k {
+            Any::Str(k) if k == key => Some(v),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+fn get_str(value: &Any, key: &str) -> String {
+    match get(value, key) {
+        Some(Any::Str(s)) => s.clone(),
+        _ => String::new(),
+    }
+}
+
+fn get_array<'a>(value: &'a Any, key: &str) -> &'a [Any] {
+    match get(value, key) {
+        Some(Any::Array(values)) => values,
+        _ => &[],
+    }
+}
+
+fn get_type_id(value: &Any) -> Option<MetacallValueId> {
+    let id = match get(get(value, "type")?, "id")? {
+        Any::Short(id) => *id as c_int,
+        Any::Int(id) => *id as c_int,
+        Any::Long(id) => *id as c_int,
+        Any::Double(id) => *id as c_int,
+        _ => return None,
+    };
+    MetacallValueId::from_raw(id)
+}
+
+pub(crate) fn parse_inspection(value: &Any) -> Result<Inspection, Error> {
+    let loaders = match value {
+        Any::Map(loaders) => loaders,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::ConversionFailed,
+                format!("Invalid inspection format: {}", value),
+            ))
+        }
+    };
+    let mut modules = Vec::new();
+    for (tag, handles) in loaders {
+        let tag = match tag {
+            Any::Str(tag) => tag,
+            _ => continue,
+        };
+        if let Any::Array(handles) = handles {
+            for handle in handles {
+                modules.push(InspectModule {
+                    tag: tag.clone(),
+                    name: get_str(handle, "name"),
+                    functions: get(handle, "scope")
+                        .map(|scope| get_array(scope, "funcs"))
+                        .unwrap_or(&[])
+                        .iter()
+                        .map(parse_function)
+                        .collect(),
+                });
+            }
+        }
+    }
+    Ok(Inspection { modules })
+}
+
+fn parse_function(func: &Any) -> InspectFunction {
+    let signature = get(func, "signature");
+    InspectFunction {
+        name: get_str(func, "name"),
+        args: signature
+            .map(|s| get_array(s, "args"))
+            .unwrap_or(&[])
+            .iter()
+            .map(|arg| InspectParameter {
+                name: get_str(arg, "name"),
+                type_id: get_type_id(arg),
+            })
+            .collect(),
+        ret: signature.and_then(|s| get(s, "ret")).and_then(get_type_id),
+        is_async: matches!(get(func, "async"), Some(Any::Bool(true))),
+    }
+}
@@ -18,303 +18,4778 @@
  *
  */
 
-use std::ffi::CString;
-use std::os::raw::{c_char, c_double, c_float, c_int, c_long, c_short, c_void};
+//! Rust bindings for MetaCall
+//!
+//! # Threading
+//!
+//! Some loaders are bound to the thread that initialized them (i.e Python keeps its
+//! interpreter state there and NodeJS runs its event loop in its own thread), so MetaCall
+//! must be initialized, used and destroyed from a single thread. The guard returned by
+//! initialize and the handles to MetaCall objects (Handle, Function) are neither Send
+//! nor Sync, so they cannot be moved to other threads; if other threads need to call
+//! into scripts, they should send their requests (i.e through a channel) to the thread
+//! that owns the guard. Functions passed to register must be Send and Sync because the
+//! scripts may call them from any of their threads. The only exception is
+//! metacall_with_timeout, which calls from a worker thread that destroy waits for.
 
-#[link(name = "metacall")] // requires libmetacall to be in $PATH
-extern "C" {
+mod inspect;
+pub mod raw;
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(feature = "tokio")]
+mod tokio_impl;
+
+pub use inspect::{
+    function_param_names, inspect, InspectFunction, InspectModule, InspectParameter, Inspection,
+};
+#[cfg(feature = "tokio")]
+pub use tokio_impl::metacall_await_tokio;
+
+use std::borrow::Borrow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::future::Future;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+use std::os::raw::{c_char, c_double, c_float, c_int, c_long, c_short, c_uchar, c_void};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+/// Declare the functions of libmetacall; the unit tests which do not call into MetaCall are
+/// built without it unless the ffi-tests feature is enabled (see the tests module), so in
+/// them the functions are replaced by stubs that panic, which are never called
+macro_rules! metacall_ffi {
+    ($($(#[$attr:meta])* fn $name:ident($($arg:ident: $type:ty),* $(,)?) $(-> $ret:ty)?;)*) => {
+        // Requires libmetacall to be in $PATH
+        #[cfg(any(not(test), feature = "ffi-tests"))]
+        #[link(name = "metacall")]
+        extern "C" {
+            $($(#[$attr])* fn $name($($arg: $type),*) $(-> $ret)?;)*
+        }
+
+        $(
+            #[cfg(all(test, not(feature = "ffi-tests")))]
+            #[allow(dead_code, unused_variables)]
+            unsafe fn $name($($arg: $type),*) $(-> $ret)? {
+                unreachable!(concat!(stringify!($name), " requires the ffi-tests feature"))
+            }
+        )*
+    };
+}
+
+metacall_ffi! {
     fn metacall_initialize() -> c_int;
+    fn metacall_flags(flags: c_int);
+    fn metacall_execution_path(tag: *const c_char, path: *const c_char) -> c_int;
+    fn metacall_is_initialized(tag: *const c_char) -> c_int;
     fn metacall_load_from_file(
         tag: *const c_char,
         paths: *mut *const u8,
         size: usize,
         handle: *mut *mut c_void,
     ) -> c_int;
+    fn metacall_load_from_memory(
+        tag: *const c_char,
+        buffer: *const c_char,
+        size: usize,
+        handle: *mut *mut c_void,
+    ) -> c_int;
+    fn metacall_load_from_package(
+        tag: *const c_char,
+        path: *const c_char,
+        handle: *mut *mut c_void,
+    ) -> c_int;
+    fn metacall_load_from_configuration(
+        path: *const c_char,
+        handle: *mut *mut c_void,
+        allocator: *mut c_void,
+    ) -> c_int;
     fn metacall_function(cfn: *const c_char) -> *mut c_void;
+    fn metacall_function_size(func: *mut c_void) -> usize;
+    fn metacall_function_async(func: *mut c_void) -> c_int;
+    fn metacall_function_parameter_type(
+        func: *mut c_void,
+        parameter: usize,
+        id: *mut c_int,
+    ) -> c_int;
+    fn metacall_handle_function(handle: *mut c_void, name: *const c_char) -> *mut c_void;
+    // Renamed because metacall_handle is already the name of a public function of this crate
+    #[link_name = "metacall_handle"]
+    fn metacall_handle_by_name(tag: *const c_char, name: *const c_char) -> *mut c_void;
+    fn metacall_clear(handle: *mut c_void) -> c_int;
+    fn metacall_handle_export(handle: *mut c_void) -> *mut c_void;
     fn metacall_destroy() -> c_int;
+    fn metacall_registerv(
+        name: *const c_char,
+        invoke: extern "C" fn(usize, *mut *mut c_void, *mut c_void) -> *mut c_void,
+        func: *mut *mut c_void,
+        return_type: MetacallValueId,
+        size: usize,
+        types: *const MetacallValueId,
+    ) -> c_int;
     fn metacallfv_s(func: *mut c_void, args: *mut *mut c_void, size: usize) -> *mut c_void;
+    fn metacallfmv(
+        func: *mut c_void,
+        keys: *mut *mut c_void,
+        values: *mut *mut c_void,
+    ) -> *mut c_void;
+    fn metacallfv_await_s(
+        func: *mut c_void,
+        args: *mut *mut c_void,
+        size: usize,
+        resolve_callback: extern "C" fn(*mut c_void, *mut c_void) -> *mut c_void,
+        reject_callback: extern "C" fn(*mut c_void, *mut c_void) -> *mut c_void,
+        data: *mut c_void,
+    ) -> *mut c_void;
+    fn metacall_value_create_null() -> *mut c_void;
     fn metacall_value_create_short(s: c_short) -> *mut c_void;
     fn metacall_value_create_int(i: c_int) -> *mut c_void;
     fn metacall_value_create_long(l: c_long) -> *mut c_void;
     fn metacall_value_create_float(f: c_float) -> *mut c_void;
     fn metacall_value_create_double(d: c_double) -> *mut c_void;
-    fn metacall_value_create_bool(b: c_int) -> *mut c_void;
+    // The boolean type of MetaCall is an unsigned char
+    fn metacall_value_create_bool(b: c_uchar) -> *mut c_void;
     fn metacall_value_create_string(st: *const c_char, ln: usize) -> *mut c_void;
     fn metacall_value_create_char(st: c_char) -> *mut c_void;
+    fn metacall_value_create_function_closure(f: *mut c_void, c: *mut c_void) -> *mut c_void;
+    fn metacall_value_create_ptr(ptr: *const c_void) -> *mut c_void;
+    fn metacall_value_create_buffer(buffer: *const c_void, size: usize) -> *mut c_void;
+    fn metacall_value_create_array(values: *const *const c_void, size: usize) -> *mut c_void;
+    fn metacall_value_create_map(tuples: *const *const c_void, size: usize) -> *mut c_void;
+    fn metacall_value_copy(v: *mut c_void) -> *mut c_void;
+    fn metacall_value_to_function(v: *mut c_void) -> *mut c_void;
     fn metacall_value_destroy(v: *mut c_void);
     fn metacall_value_id(v: *mut c_void) -> c_int;
+    fn metacall_value_size(v: *mut c_void) -> usize;
+    fn metacall_value_count(v: *mut c_void) -> usize;
     fn metacall_value_to_string(v: *mut c_void) -> *mut c_char;
     fn metacall_value_to_char(v: *mut c_void) -> c_char;
     fn metacall_value_to_short(v: *mut c_void) -> c_short;
     fn metacall_value_to_int(v: *mut c_void) -> c_int;
     fn metacall_value_to_long(v: *mut c_void) -> c_long;
-    fn metacall_value_to_bool(v: *mut c_void) -> c_int;
+    fn metacall_value_to_bool(v: *mut c_void) -> c_uchar;
     fn metacall_value_to_float(v: *mut c_void) -> c_float;
     fn metacall_value_to_double(v: *mut c_void) -> c_double;
+    fn metacall_value_to_buffer(v: *mut c_void) -> *mut c_void;
+    fn metacall_value_to_array(v: *mut c_void) -> *mut *mut c_void;
+    fn metacall_value_to_map(v: *mut c_void) -> *mut *mut c_void;
+    fn metacall_value_to_ptr(v: *mut c_void) -> *mut c_void;
+    fn metacall_allocator_create(allocator_id: c_int, ctx: *mut c_void) -> *mut c_void;
+    fn metacall_allocator_free(allocator: *mut c_void, data: *mut c_void);
+    fn metacall_allocator_destroy(allocator: *mut c_void);
+    fn metacall_serial() -> *const c_char;
+    fn metacall_inspect(size: *mut usize, allocator: *mut c_void) -> *mut c_char;
+    fn metacall_serialize(
+        name: *const c_char,
+        v: *mut c_void,
+        size: *mut usize,
+        allocator: *mut c_void,
+    ) -> *mut c_char;
+    fn metacall_deserialize(
+        name: *const c_char,
+        buffer: *const c_char,
+        size: usize,
+        allocator: *mut c_void,
+    ) -> *mut c_void;
 }
 
-#[derive(Debug)]
-pub struct Error(String);
+// Standard C allocation functions used for building MetaCall allocators
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn realloc(data: *mut c_void, size: usize) -> *mut c_void;
+    fn free(data: *mut c_void);
+}
 
-/// Enum of all possible Metacall types to allow for safe conversion between them and c_types
-#[derive(Debug)]
-pub enum Any {
-    Null,              // from c_null
-    Short(i16),        // from c_short
-    Int(i32),          // from c_int
-    Long(i64),         // from c_long
-    Float(f32),        // from c_float
-    Double(f64),       // from c_double
-    Bool(bool),        // from c_bool
-    Char(char),        // from c_char
-    Str(String),       // from *const u8 (null terminated)
-    Array(Vec<Any>),   // from *mut *mut c_void
-    Buffer(Vec<u8>),   // from *const u8 (non-null terminated) (raw binary data)
-    Pointer(Box<Any>), // from *mut c_void
-    Function(Box<fn(Any) -> Any>), // from a C function pointer
-                       // METACALL_FUTURE
+/// Information of the shared object containing an address, filled by dladdr
+#[cfg(unix)]
+#[repr(C)]
+struct DlInfo {
+    dli_fname: *const c_char,
+    dli_fbase: *mut c_void,
+    dli_sname: *const c_char,
+    dli_saddr: *mut c_void,
 }
 
-impl From<c_short> for Any {
-    fn from(val: c_short) -> Self {
-        Any::Short(val)
+// Used for finding the directory of libmetacall, where the loader plugins are installed
+#[cfg(unix)]
+extern "C" {
+    fn dladdr(addr: *const c_void, info: *mut DlInfo) -> c_int;
+}
+
+// METACALL_ALLOCATOR_STD from enum metacall_allocator_id of metacall_allocator.h
+const METACALL_ALLOCATOR_STD: c_int = 0;
+
+/// Mimics struct metacall_allocator_std_type of metacall_allocator.h
+#[repr(C)]
+struct MetacallAllocatorStd {
+    malloc: unsafe extern "C" fn(usize) -> *mut c_void,
+    realloc: unsafe extern "C" fn(*mut c_void, usize) -> *mut c_void,
+    free: unsafe extern "C" fn(*mut c_void),
+}
+
+/// Allocator required by the MetaCall functions that allocate memory on behalf of the
+/// caller, it uses the standard C allocator, so the memory can be released with free
+struct Allocator(*mut c_void);
+
+impl Allocator {
+    fn new() -> Result<Allocator, Error> {
+        // The context is copied by MetaCall, so it does not need to outlive the allocator
+        let mut ctx = MetacallAllocatorStd {
+            malloc,
+            realloc,
+            free,
+        };
+        let allocator = unsafe {
+            metacall_allocator_create(
+                METACALL_ALLOCATOR_STD,
+                &mut ctx as *mut MetacallAllocatorStd as *mut c_void,
+            )
+        };
+        if allocator.is_null() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                "Failed to create MetaCall allocator",
+            ));
+        }
+        Ok(Allocator(allocator))
     }
 }
-impl From<c_int> for Any {
-    fn from(val: c_int) -> Self {
-        Any::Int(val)
+
+impl Drop for Allocator {
+    fn drop(&mut self) {
+        unsafe { metacall_allocator_destroy(self.0) }
     }
 }
-impl From<c_long> for Any {
-    fn from(val: c_long) -> Self {
-        Any::Long(val)
+
+/// Type identifiers of MetaCall values, mimicking enum metacall_value_id of metacall_value.h
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetacallValueId {
+    Bool = 0,
+    Char = 1,
+    Short = 2,
+    Int = 3,
+    Long = 4,
+    Float = 5,
+    Double = 6,
+    String = 7,
+    Buffer = 8,
+    Array = 9,
+    Map = 10,
+    Ptr = 11,
+    Future = 12,
+    Function = 13,
+    Null = 14,
+    Class = 15,
+    Object = 16,
+    // Used for registering dynamically typed parameters (no type is assigned)
+    Invalid = 18,
+}
+
+impl MetacallValueId {
+    /// Convert the raw id returned by MetaCall, this is the only place where the
+    /// numeric values are matched, so any mismatch with metacall.h is localized here
+    fn from_raw(id: c_int) -> Option<MetacallValueId> {
+        match id {
+            0 => Some(MetacallValueId::Bool),
+            1 => Some(MetacallValueId::Char),
+            2 => Some(MetacallValueId::Short),
+            3 => Some(MetacallValueId::Int),
+            4 => Some(MetacallValueId::Long),
+            5 => Some(MetacallValueId::Float),
+            6 => Some(MetacallValueId::Double),
+            7 => Some(MetacallValueId::String),
+            8 => Some(MetacallValueId::Buffer),
+            9 => Some(MetacallValueId::Array),
+            10 => Some(MetacallValueId::Map),
+            11 => Some(MetacallValueId::Ptr),
+            12 => Some(MetacallValueId::Future),
+            13 => Some(MetacallValueId::Function),
+            14 => Some(MetacallValueId::Null),
+            15 => Some(MetacallValueId::Class),
+            16 => Some(MetacallValueId::Object),
+            _ => None,
+        }
+    }
+
+    /// Human readable name of the type (i.e for diagnostics)
+    pub fn name(self) -> &'static str {
+        match self {
+            MetacallValueId::Bool => "bool",
+            MetacallValueId::Char => "char",
+            MetacallValueId::Short => "short",
+            MetacallValueId::Int => "int",
+            MetacallValueId::Long => "long",
+            MetacallValueId::Float => "float",
+            MetacallValueId::Double => "double",
+            MetacallValueId::String => "string",
+            MetacallValueId::Buffer => "buffer",
+            MetacallValueId::Array => "array",
+            MetacallValueId::Map => "map",
+            MetacallValueId::Ptr => "pointer",
+            MetacallValueId::Future => "future",
+            MetacallValueId::Function => "function",
+            MetacallValueId::Null => "null",
+            MetacallValueId::Class => "class",
+            MetacallValueId::Object => "object",
+            MetacallValueId::Invalid => "invalid",
+        }
     }
 }
-impl From<c_char> for Any {
-    fn from(val: c_char) -> Self {
-        Any::Char(val as u8 as char)
+
+/// Category of an Error, so callers can handle the failures differently (i.e retrying
+/// with another loader) without matching on the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The loader of the tag is not available (i.e it was not built or it failed to initialize)
+    LoaderNotFound,
+    /// The function (or the module containing it) is not loaded
+    FunctionNotFound,
+    /// The loader is available but it failed to load the script (i.e a syntax error)
+    LoadFailed,
+    /// The call failed (i.e the script raised an exception or the arguments were invalid)
+    CallFailed,
+    /// A value could not be converted between Rust and MetaCall
+    ConversionFailed,
+    /// Any other failure of MetaCall (i.e initialization or registration)
+    Other,
+}
+
+/// Error returned by the fallible MetaCall operations, it contains the kind and a
+/// description of the failure (which is what Display prints)
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl Error {
+    /// Create an error, i.e for returning it from the functions given to register_fallible
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Error {
+        Error {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Category of the failure
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Description of the failure
+    pub fn message(&self) -> &str {
+        &self.message
     }
 }
-impl From<bool> for Any {
-    fn from(val: bool) -> Self {
-        Any::Bool(val)
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
     }
 }
-impl From<c_float> for Any {
-    fn from(val: c_float) -> Self {
-        Any::Float(val)
+
+impl std::error::Error for Error {}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+    // Number of operations of the crate running on the thread, see entry
+    static ENTRY_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Message of the last error returned by an operation of the crate on the current thread
+/// (loading, calling, registering, ...), None if none of them has failed yet
+///
+/// This version of MetaCall does not keep an error state that could be queried after a failure,
+/// the loaders print their errors (i.e Python exceptions) and the functions only report that
+/// they failed; so this is the error state of the crate instead, which is thread local like the
+/// errors themselves and is not cleared by the operations that succeed
+pub fn last_error() -> Option<String> {
+    LAST_ERROR.with(|last| last.borrow().clone())
+}
+
+/// Record an error returned to the caller as the last error of the thread
+fn fail(e: Error) -> Error {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(e.message.clone()));
+    e
+}
+
+/// Run a public operation recording its error with fail, the operations can be built on
+/// others (i.e call_or_load looks the function up before loading its script), so only the
+/// outermost one records its error, the errors handled by the operation itself are not
+fn entry<T>(f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    // Restores the depth even if the operation panics (i.e in a registered function)
+    struct Depth;
+    impl Drop for Depth {
+        fn drop(&mut self) {
+            ENTRY_DEPTH.with(|depth| depth.set(depth.get() - 1));
+        }
+    }
+    let outermost = ENTRY_DEPTH.with(|depth| {
+        depth.set(depth.get() + 1);
+        depth.get() == 1
+    });
+    let _depth = Depth;
+    let result = f();
+    if outermost {
+        result.map_err(fail)
+    } else {
+        result
     }
 }
-impl From<c_double> for Any {
-    fn from(val: c_double) -> Self {
-        Any::Double(val)
+
+/// Opaque pointer to native data (i.e a C struct handle returned by a script), Rust never
+/// dereferences it, so it can be safely passed around and sent back to the scripts, but
+/// its lifetime is managed by the script which created it, so it may dangle once freed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpaquePointer(*mut c_void);
+
+// The pointer is never dereferenced by Rust, it is only an address passed between scripts
+unsafe impl Send for OpaquePointer {}
+unsafe impl Sync for OpaquePointer {}
+
+impl OpaquePointer {
+    /// Wrap a raw pointer so it can be passed to scripts as Any::Pointer
+    ///
+    /// # Safety
+    ///
+    /// The scripts receiving the pointer may dereference it, so it must point to
+    /// data valid for them while they use it
+    pub unsafe fn new(ptr: *mut c_void) -> OpaquePointer {
+        OpaquePointer(ptr)
+    }
+
+    pub fn as_ptr(&self) -> *mut c_void {
+        self.0
     }
 }
 
-pub fn initialize() -> Result<(), &'static str> {
-    if unsafe { metacall_initialize() } != 0 {
-        Err("Metacall failed to initialize")
-    } else {
-        Ok(())
+/// Function returned by a script (i.e a closure), it can be called with call_value or passed back
+/// to the scripts; it holds a reference to the function, so it stays alive (even if the script
+/// does not keep it) until the last clone of it is dropped, clones share the same reference
+#[derive(Debug, Clone)]
+pub struct FunctionValue(Arc<FunctionValueRef>);
+
+#[derive(Debug)]
+struct FunctionValueRef {
+    value: *mut c_void,
+    function: *mut c_void,
+    // Generation of MetaCall the value belongs to (see GENERATION)
+    generation: usize,
+}
+
+impl FunctionValueRef {
+    /// Keep a copy of a borrowed function value
+    unsafe fn new(value: *mut c_void) -> FunctionValueRef {
+        let value = metacall_value_copy(value);
+        FunctionValueRef {
+            value,
+            function: metacall_value_to_function(value),
+            generation: GENERATION.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Check that the MetaCall instance which created the value has not been destroyed
+    fn alive(&self) -> Result<(), Error> {
+        if *initialized() > 0 && GENERATION.load(Ordering::SeqCst) == self.generation {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::CallFailed,
+                "The function value was destroyed along with MetaCall",
+            ))
+        }
     }
 }
 
-pub fn load_from_file(
-    tag: &str,
-    scripts: impl IntoIterator<Item = impl AsRef<str>>,
-) -> Result<(), &'static str> {
-    // allocate a safe C String
-    let ctag = CString::new(tag).expect("Conversion to C String failed");
-    let owned_scripts: Vec<_> = scripts
-        .into_iter()
-        .map(|x| CString::new(x.as_ref()).expect("Conversion to C String failed"))
-        .collect();
-    let mut ref_c_scripts: Vec<_> = owned_scripts
-        .iter()
-        .map(|s| s.as_ptr())
-        .map(|p| p as *const u8)
-        .collect();
-    if unsafe {
-        metacall_load_from_file(
-            ctag.as_ptr(),
-            ref_c_scripts.as_mut_ptr(),
-            ref_c_scripts.len(),
-            std::ptr::null_mut(),
-        )
-    } != 0
-    {
-        return Err("MetaCall failed to load script");
+impl Drop for FunctionValueRef {
+    fn drop(&mut self) {
+        // The lock keeps MetaCall from being destroyed while the value is destroyed, values
+        // which outlive their MetaCall instance were already released by it, so they are leaked
+        let count = initialized();
+        if *count > 0 && GENERATION.load(Ordering::SeqCst) == self.generation {
+            // SAFETY: the value is a copy owned by this reference, destroying it decrements
+            // the reference count of the function, which is released with its last value
+            unsafe { metacall_value_destroy(self.value) }
+        }
     }
-    Ok(())
 }
 
-// Possible types as variants in Rust
-pub fn metacall<'a>(
-    func: &str,
-    args: impl IntoIterator<Item = &'a Any>,
-) -> Result<Any, &'static str> {
-    let c_function = CString::new(func).expect("Conversion to C String failed");
-    unsafe {
-        // let c_func = metacall_function(c_function.as_ptr());
-        let c_func: *mut c_void = metacall_function(c_function.as_ptr());
-        if c_func.is_null() {
-            return Err("Function Not Found");
+// Any must be Send so values can be moved between threads (i.e metacall_await_tokio), the
+// value is never accessed by Rust, it is only destroyed while holding the initialization
+// lock, and calls or copies of it fail once its MetaCall instance is destroyed (see alive)
+unsafe impl Send for FunctionValueRef {}
+unsafe impl Sync for FunctionValueRef {}
+
+impl PartialEq for FunctionValue {
+    fn eq(&self, other: &FunctionValue) -> bool {
+        self.0.function == other.0.function
+    }
+}
+
+/// Enum of all possible Metacall types to allow for safe conversion between them and c_types
+///
+/// Equality compares the variant and its contents, so values of different variants are never
+/// equal (i.e `Any::Int(1) != Any::Long(1)`), floats are compared with `==` (not bitwise), so
+/// `NaN` is not equal to itself, and functions are equal only if they point to the same function
+///
+/// MetaCall chars are a single byte, so only ASCII chars are passed as chars, the rest are passed
+/// as a string of one char; C chars outside of ASCII are not valid text, so they become a buffer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Any {
+    Null,                          // from c_null
+    Short(i16),                    // from c_short
+    Int(i32),                      // from c_int
+    Long(i64),                     // from c_long
+    Float(f32),                    // from c_float
+    Double(f64),                   // from c_double
+    Bool(bool),                    // from c_bool
+    Char(char),                    // from c_char
+    Str(String),                   // from *const u8 (null terminated)
+    Array(Vec<Any>),               // from *mut *mut c_void
+    Map(Vec<(Any, Any)>),          // from *mut *mut c_void (array of key / value pairs)
+    Buffer(Vec<u8>),               // from *const u8 (non-null terminated) (raw binary data)
+    Pointer(OpaquePointer),        // from *mut c_void
+    Function(Box<fn(Any) -> Any>), // from a C function pointer
+    FunctionValue(FunctionValue),  // from METACALL_FUNCTION (i.e a closure returned by a script)
+                                   // METACALL_FUTURE
+}
+
+/// What to do when an integer does not fit into the variant it is converted into, the From
+/// implementations never lose information (u64 and usize implement TryFrom, which fails)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Fail with an error
+    Error,
+    /// Clamp to the minimum or maximum of the variant
+    Saturate,
+    /// Keep the lowest bits (two's complement wrapping, like `as`)
+    Wrap,
+}
+
+macro_rules! impl_any_integer_constructor {
+    ($name:ident, $variant:ident, $type:ty) => {
+        #[doc = concat!("Create an Any::", stringify!($variant), " handling overflows as specified")]
+        pub fn $name(val: i128, overflow: Overflow) -> Result<Any, Error> {
+            match <$type>::try_from(val) {
+                Ok(x) => Ok(Any::$variant(x)),
+                Err(_) => match overflow {
+                    Overflow::Error => Err(Error::new(
+                        ErrorKind::ConversionFailed,
+                        format!("Integer {} does not fit into {}", val, stringify!($type)),
+                    )),
+                    Overflow::Saturate if val < 0 => Ok(Any::$variant(<$type>::MIN)),
+                    Overflow::Saturate => Ok(Any::$variant(<$type>::MAX)),
+                    Overflow::Wrap => Ok(Any::$variant(val as $type)),
+                },
+            }
         }
-        let mut c_args: Vec<*mut c_void> = args
-            .into_iter()
-            .map(|arg| match arg {
-                Any::Short(x) => metacall_value_create_short(*x),
-                Any::Int(x) => metacall_value_create_int(*x),
-                Any::Long(x) => metacall_value_create_long(*x),
-                Any::Float(x) => metacall_value_create_float(*x),
-                Any::Double(x) => metacall_value_create_double(*x),
-                Any::Bool(x) => metacall_value_create_bool(*x as c_int),
-                Any::Char(x) => metacall_value_create_char(*x as c_char),
-                Any::Str(x) => {
-                    let st = CString::new(x.as_str()).expect("can't convert to c str");
-                    metacall_value_create_string(st.as_ptr(), x.len())
-                }
-                _ => todo!(),
-            })
-            .collect();
-        let ret: *mut c_void = metacallfv_s(c_func, c_args.as_mut_ptr(), c_args.len());
-        let mut rt = Any::Null;
-        if !ret.is_null() {
-            /* TODO: This should be done by an enum or something mimicking the enum in metacall.h */
-            match metacall_value_id(ret) {
-                0 => {
-                    rt = Any::Bool(metacall_value_to_bool(ret) != 0);
-                }
-                1 => {
-                    rt = Any::Char(metacall_value_to_char(ret) as u8 as char);
-                }
-                2 => {
-                    rt = Any::Short(metacall_value_to_short(ret));
-                }
-                3 => {
-                    rt = Any::Int(metacall_value_to_int(ret));
-                }
-                4 => {
-                    rt = Any::Long(metacall_value_to_long(ret));
-                }
-                5 => {
-                    rt = Any::Float(metacall_value_to_float(ret));
-                }
-                6 => {
-                    rt = Any::Double(metacall_value_to_double(ret));
-                }
-                7 => {
-                    let st = std::ffi::CStr::from_ptr(metacall_value_to_string(ret));
-                    rt = Any::Str(String::from(
-                        st.to_str().expect("couldn't convert CStr to &str"),
-                    ));
-                }
-                8 => {
-                    // METACALL_BUFFER
-                }
-                9 => {
-                    // METACALL_ARRAY
-                }
-                10 => {
-                    // METACALL_MAP
-                }
-                11 => {
-                    // METACALL_PTR
-                }
-                12 => {
-                    // METACALL_FUTURE
-                }
-                13 => {
-                    // METACALL_FUNCTION
+    };
+}
+
+impl Any {
+    impl_any_integer_constructor!(short_from, Short, i16);
+    impl_any_integer_constructor!(int_from, Int, i32);
+    impl_any_integer_constructor!(long_from, Long, i64);
+}
+
+/// Numbers that can be packed into an Any::Buffer with Any::packed, FORMAT is the code of the
+/// type in the struct and array modules of Python, which numpy also accepts as dtype
+pub trait Packed: Copy {
+    const FORMAT: &'static str;
+
+    /// Append the native endian bytes of the number
+    fn write_ne(self, buffer: &mut Vec<u8>);
+
+    /// Read a number from its native endian bytes, which have the size of the type
+    fn read_ne(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_packed {
+    ($($type:ty => $format:expr),+) => {
+        $(
+            impl Packed for $type {
+                const FORMAT: &'static str = $format;
+
+                fn write_ne(self, buffer: &mut Vec<u8>) {
+                    buffer.extend_from_slice(&self.to_ne_bytes());
                 }
-                14 => {
-                    rt = Any::Null;
+
+                fn read_ne(bytes: &[u8]) -> Self {
+                    <$type>::from_ne_bytes(TryFrom::try_from(bytes).unwrap())
                 }
-                _ => {}
             }
-            metacall_value_destroy(ret);
+        )+
+    };
+}
+
+impl_packed!(
+    i8 => "b", u8 => "B", i16 => "h", u16 => "H", i32 => "i",
+    u32 => "I", i64 => "q", u64 => "Q", f32 => "f", f64 => "d"
+);
+
+// Converting a Vec<i64> into an Any::Array creates one MetaCall value per element, which
+// dominates the calls with large numeric arrays, a buffer is a single value copied at once
+impl Any {
+    /// Pack numbers into a buffer of their native endian bytes, so they are passed as a
+    /// single value instead of an array with one value per element; the scripts receive
+    /// the raw bytes, which they have to interpret with the format of the type (Packed::FORMAT):
+    ///
+    /// - Python receives `bytes`, i.e `numpy.frombuffer(data, dtype='q')` or `array.array('q', data)`
+    /// - NodeJS receives a `Buffer`, i.e `new BigInt64Array(data.buffer, data.byteOffset, data.length / 8)`
+    ///
+    /// ```no_run
+    /// use metacall::Any;
+    ///
+    /// let data = Any::packed(&[1i64, 2, 3]);
+    /// assert_eq!(data.as_buffer().map(<[u8]>::len), Some(24));
+    /// assert_eq!(data.unpacked::<i64>(), Some(vec![1, 2, 3]));
+    /// ```
+    pub fn packed<T: Packed>(values: &[T]) -> Any {
+        let mut buffer = Vec::with_capacity(std::mem::size_of_val(values));
+        for value in values {
+            value.write_ne(&mut buffer);
         }
-        for arg in c_args {
-            metacall_value_destroy(arg);
+        Any::Buffer(buffer)
+    }
+
+    /// Unpack a buffer of native endian numbers (i.e created with packed or returned by
+    /// numpy's `tobytes`), None if the value is not a buffer or if its size is not a
+    /// multiple of the size of the type
+    pub fn unpacked<T: Packed>(&self) -> Option<Vec<T>> {
+        let buffer = self.as_buffer()?;
+        let size = std::mem::size_of::<T>();
+        if buffer.len() % size != 0 {
+            return None;
         }
-        Ok(rt)
+        Some(buffer.chunks_exact(size).map(T::read_ne).collect())
     }
 }
 
-pub fn destroy() {
-    unsafe {
-        metacall_destroy();
+// Each loader produces the variant matching its own representation of a value, so the same
+// logical number may arrive with different widths (i.e Python integers are Any::Long while
+// other loaders may produce Any::Int), these helpers allow reading numbers regardless of that
+impl Any {
+    /// Integer value of any integer variant (Short, Int or Long)
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Any::Short(x) => Some(*x as i64),
+            Any::Int(x) => Some(*x as i64),
+            Any::Long(x) => Some(*x),
+            _ => None,
+        }
     }
-}
 
-/// Doc test to check if the code can build an run
-#[cfg(test)]
-mod tests {
-    struct Defer<F: FnOnce()>(Option<F>);
+    /// Floating point value of any numeric variant, integers
+    /// bigger than 2^53 lose precision in the conversion
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Any::Float(x) => Some(*x as f64),
+            Any::Double(x) => Some(*x),
+            _ => self.as_i64().map(|x| x as f64),
+        }
+    }
 
-    impl<F: FnOnce()> Drop for Defer<F> {
-        fn drop(&mut self) {
-            if let Some(f) = self.0.take() {
-                f()
-            }
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Any::Bool(x) => Some(*x),
+            _ => None,
         }
     }
 
-    /// Defer execution of a closure until the constructed value is dropped
-    /// Works at the end of the scope or manual drop() function
-    pub fn defer<F: FnOnce()>(f: F) -> impl Drop {
-        Defer(Some(f))
+    pub fn as_char(&self) -> Option<char> {
+        match self {
+            Any::Char(x) => Some(*x),
+            _ => None,
+        }
     }
 
-    #[test]
-    fn test_defer() {
-        use std::cell::RefCell;
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Any::Str(x) => Some(x),
+            _ => None,
+        }
+    }
 
-        let i = RefCell::new(0);
+    pub fn as_array(&self) -> Option<&[Any]> {
+        match self {
+            Any::Array(x) => Some(x),
+            _ => None,
+        }
+    }
 
-        {
-            let _d = defer(|| *i.borrow_mut() += 1);
-            assert_eq!(*i.borrow(), 0);
+    pub fn as_map(&self) -> Option<&[(Any, Any)]> {
+        match self {
+            Any::Map(x) => Some(x),
+            _ => None,
         }
+    }
 
-        assert_eq!(*i.borrow(), 1);
+    pub fn as_buffer(&self) -> Option<&[u8]> {
+        match self {
+            Any::Buffer(x) => Some(x),
+            _ => None,
+        }
     }
 
-    #[test]
-    fn test_metacall() {
-        let _d = defer(crate::destroy);
+    pub fn as_pointer(&self) -> Option<OpaquePointer> {
+        match self {
+            Any::Pointer(x) => Some(*x),
+            _ => None,
+        }
+    }
+}
 
-        match crate::initialize() {
-            Err(e) => {
-                println!("{}", e);
-                panic!();
-            }
-            _ => println!(" Hello World Metacall created "),
+// Predicates checking the variant of a value, without the conversions of the accessors
+impl Any {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Any::Null)
+    }
+
+    pub fn is_bool(&self) -> bool {
+        matches!(self, Any::Bool(_))
+    }
+
+    pub fn is_char(&self) -> bool {
+        matches!(self, Any::Char(_))
+    }
+
+    pub fn is_str(&self) -> bool {
+        matches!(self, Any::Str(_))
+    }
+
+    /// True for any of the numeric variants (Short, Int, Long, Float or Double)
+    pub fn is_number(&self) -> bool {
+        matches!(
+            self,
+            Any::Short(_) | Any::Int(_) | Any::Long(_) | Any::Float(_) | Any::Double(_)
+        )
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(self, Any::Array(_))
+    }
+
+    pub fn is_map(&self) -> bool {
+        matches!(self, Any::Map(_))
+    }
+
+    pub fn is_buffer(&self) -> bool {
+        matches!(self, Any::Buffer(_))
+    }
+
+    pub fn is_pointer(&self) -> bool {
+        matches!(self, Any::Pointer(_))
+    }
+
+    pub fn is_function(&self) -> bool {
+        matches!(self, Any::Function(_) | Any::FunctionValue(_))
+    }
+}
+
+// Access to the elements of arrays and maps, and conversions of whole collections
+impl Any {
+    /// Element of an array by index, it is None when out of bounds or if the value is not an
+    /// array, i.e for reading a list returned by a script:
+    ///
+    /// ```no_run
+    /// use metacall::Any;
+    ///
+    /// let list = Any::Array(vec![Any::Long(1), Any::Str("two".to_string())]);
+    /// assert_eq!(list.get(1).and_then(Any::as_str), Some("two"));
+    /// assert_eq!(list.get(2), None);
+    /// ```
+    pub fn get(&self, index: usize) -> Option<&Any> {
+        match self {
+            Any::Array(x) => x.get(index),
+            _ => None,
         }
+    }
 
-        let scripts = ["test.mock"];
+    /// Number of elements of an array or entries of a map, None for the rest of variants
+    pub fn len(&self) -> Option<usize> {
+        match self {
+            Any::Array(x) => Some(x.len()),
+            Any::Map(x) => Some(x.len()),
+            _ => None,
+        }
+    }
 
-        if let Err(e) = crate::load_from_file("mock", &scripts) {
-            println!("{}", e);
-            panic!();
+    /// Whether an array or a map has no elements, None for the rest of variants
+    pub fn is_empty(&self) -> Option<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Consume an array iterating over its elements without copying them,
+    /// the iterator is empty if the value is not an array
+    pub fn into_array_iter(self) -> std::vec::IntoIter<Any> {
+        match self {
+            Any::Array(x) => x.into_iter(),
+            _ => Vec::new().into_iter(),
         }
+    }
 
-        match crate::metacall("new_args", &[crate::Any::Str("a".to_string())]) {
-            Ok(ret) => match ret {
-                crate::Any::Str(value) => {
-                    assert_eq!("Hello World".to_string(), value);
-                    println!("Result: {}", value);
-                }
-                _ => {
-                    assert_eq!(0, 1);
-                    panic!();
+    /// Consume a map iterating over its entries (key and value) without copying
+    /// them, the iterator is empty if the value is not a map
+    pub fn into_map_iter(self) -> std::vec::IntoIter<(Any, Any)> {
+        match self {
+            Any::Map(x) => x.into_iter(),
+            _ => Vec::new().into_iter(),
+        }
+    }
+
+    /// Convert an array whose elements are all of the same type into a vector, converting
+    /// each element with TryFrom (i.e `metacall("f", &[])?.try_into_vec::<i64>()?`), it
+    /// fails if the value is not an array or if any of its elements cannot be converted
+    pub fn try_into_vec<T>(self) -> Result<Vec<T>, Error>
+    where
+        T: TryFrom<Any>,
+        T::Error: fmt::Display,
+    {
+        match self {
+            Any::Array(x) => x
+                .into_iter()
+                .enumerate()
+                .map(|(i, element)| {
+                    T::try_from(element).map_err(|e| {
+                        Error::new(
+                            ErrorKind::ConversionFailed,
+                            format!("Invalid element {} of the array: {}", i, e),
+                        )
+                    })
+                })
+                .collect(),
+            _ => Err(conversion_error(&self, "Vec")),
+        }
+    }
+
+    /// Combine two maps or two arrays (i.e for overlaying the options passed to a script):
+    /// the entries of the other map replace the ones with the same key (keeping their
+    /// position) and the rest are appended, arrays are concatenated; it fails for any
+    /// other combination of variants
+    ///
+    /// ```no_run
+    /// use metacall::Any;
+    ///
+    /// let defaults = Any::Map(vec![("a".into(), 1.into()), ("b".into(), 2.into())]);
+    /// let options = Any::Map(vec![("b".into(), 3.into()), ("c".into(), 4.into())]);
+    /// assert_eq!(
+    ///     defaults.merge(options).unwrap(),
+    ///     Any::Map(vec![("a".into(), 1.into()), ("b".into(), 3.into()), ("c".into(), 4.into())])
+    /// );
+    /// ```
+    pub fn merge(self, other: Any) -> Result<Any, Error> {
+        match (self, other) {
+            (Any::Map(mut x), Any::Map(y)) => {
+                for (key, value) in y {
+                    match x.iter_mut().find(|(k, _)| *k == key) {
+                        Some(entry) => entry.1 = value,
+                        None => x.push((key, value)),
+                    }
                 }
-            },
-            Err(e) => {
+                Ok(Any::Map(x))
+            }
+            (Any::Array(mut x), Any::Array(y)) => {
+                x.extend(y);
+                Ok(Any::Array(x))
+            }
+            (x, y) => Err(Error::new(
+                ErrorKind::ConversionFailed,
+                format!(
+                    "Cannot merge {} {:?} with {} {:?}, only two maps or two arrays can be merged",
+                    x.type_name(),
+                    x,
+                    y.type_name(),
+                    y
+                ),
+            )),
+        }
+    }
+}
+
+// Descriptions of the type of a value
+impl Any {
+    /// Type of the MetaCall value the Any is converted into when it is passed to a script,
+    /// i.e for building the types of register (chars outside of ASCII are strings, see Any)
+    pub fn value_id(&self) -> MetacallValueId {
+        match self {
+            Any::Null => MetacallValueId::Null,
+            Any::Short(_) => MetacallValueId::Short,
+            Any::Int(_) => MetacallValueId::Int,
+            Any::Long(_) => MetacallValueId::Long,
+            Any::Float(_) => MetacallValueId::Float,
+            Any::Double(_) => MetacallValueId::Double,
+            Any::Bool(_) => MetacallValueId::Bool,
+            Any::Char(x) if x.is_ascii() => MetacallValueId::Char,
+            Any::Char(_) | Any::Str(_) => MetacallValueId::String,
+            Any::Array(_) => MetacallValueId::Array,
+            Any::Map(_) => MetacallValueId::Map,
+            Any::Buffer(_) => MetacallValueId::Buffer,
+            Any::Pointer(_) => MetacallValueId::Ptr,
+            Any::Function(_) | Any::FunctionValue(_) => MetacallValueId::Function,
+        }
+    }
+
+    /// Human readable name of the type of the value (i.e for diagnostics), it is the name of
+    /// its MetaCall type (see value_id), except for chars, which are always named char
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Any::Char(_) => "char",
+            _ => self.value_id().name(),
+        }
+    }
+}
+
+/// The default value is Any::Null (i.e for placeholders in structs deriving Default)
+impl Default for Any {
+    fn default() -> Self {
+        Any::Null
+    }
+}
+
+/// Renders the value for humans (i.e logs), strings are written raw and
+/// the contents of arrays and maps are formatted recursively
+impl fmt::Display for Any {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Any::Null => f.write_str("null"),
+            Any::Short(x) => write!(f, "{}", x),
+            Any::Int(x) => write!(f, "{}", x),
+            Any::Long(x) => write!(f, "{}", x),
+            Any::Float(x) => write!(f, "{}", x),
+            Any::Double(x) => write!(f, "{}", x),
+            Any::Bool(x) => write!(f, "{}", x),
+            Any::Char(x) => write!(f, "{}", x),
+            Any::Str(x) => f.write_str(x),
+            Any::Array(x) => {
+                f.write_str("[")?;
+                for (i, v) in x.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                f.write_str("]")
+            }
+            Any::Map(x) => {
+                f.write_str("{")?;
+                for (i, (k, v)) in x.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}: {}", k, v)?;
+                }
+                f.write_str("}")
+            }
+            Any::Buffer(x) => write!(f, "<buffer of {} bytes>", x.len()),
+            Any::Pointer(x) => write!(f, "<pointer {:p}>", x.as_ptr()),
+            Any::Function(_) | Any::FunctionValue(_) => f.write_str("<function>"),
+        }
+    }
+}
+
+impl From<c_short> for Any {
+    fn from(val: c_short) -> Self {
+        Any::Short(val)
+    }
+}
+impl From<c_int> for Any {
+    fn from(val: c_int) -> Self {
+        Any::Int(val)
+    }
+}
+impl From<c_long> for Any {
+    fn from(val: c_long) -> Self {
+        Any::Long(val)
+    }
+}
+impl From<c_char> for Any {
+    fn from(val: c_char) -> Self {
+        let byte = val as u8;
+        if byte.is_ascii() {
+            Any::Char(byte as char)
+        } else {
+            Any::Buffer(vec![byte])
+        }
+    }
+}
+impl From<char> for Any {
+    fn from(val: char) -> Self {
+        Any::Char(val)
+    }
+}
+impl From<bool> for Any {
+    fn from(val: bool) -> Self {
+        Any::Bool(val)
+    }
+}
+impl From<()> for Any {
+    fn from(_: ()) -> Self {
+        Any::Null
+    }
+}
+// Float literals without suffix are f64, so they become Any::Double (i.e in metacall!),
+// use the f32 suffix (i.e `1.5f32`) to pass a single precision Any::Float instead
+impl From<c_float> for Any {
+    fn from(val: c_float) -> Self {
+        Any::Float(val)
+    }
+}
+impl From<c_double> for Any {
+    fn from(val: c_double) -> Self {
+        Any::Double(val)
+    }
+}
+// Unsigned integers are converted into the narrowest signed variant that can hold all their
+// values, u64 and usize can be bigger than i64::MAX, so they implement TryFrom; i8 and u8
+// are not implemented because one of them is c_char (converted into Any::Char) and u8 is
+// converted into a buffer in Vec<u8>, convert them into i16 first to pass them as numbers
+impl From<u16> for Any {
+    fn from(val: u16) -> Self {
+        Any::Int(val as i32)
+    }
+}
+impl From<u32> for Any {
+    fn from(val: u32) -> Self {
+        Any::Long(val as i64)
+    }
+}
+// A blanket implementation over AsRef<str> is not possible because it
+// would conflict with the rest of From implementations of Any
+impl From<String> for Any {
+    fn from(val: String) -> Self {
+        Any::Str(val)
+    }
+}
+impl From<&str> for Any {
+    fn from(val: &str) -> Self {
+        Any::Str(val.to_string())
+    }
+}
+// Bytes are binary data, so they are converted into a buffer instead of an array (u8 is
+// not convertible into Any, so these do not overlap with the implementations for arrays)
+impl From<Vec<u8>> for Any {
+    fn from(val: Vec<u8>) -> Self {
+        Any::Buffer(val)
+    }
+}
+impl From<&[u8]> for Any {
+    fn from(val: &[u8]) -> Self {
+        Any::Buffer(val.to_vec())
+    }
+}
+impl<T: Into<Any>> From<Vec<T>> for Any {
+    fn from(val: Vec<T>) -> Self {
+        Any::Array(val.into_iter().map(Into::into).collect())
+    }
+}
+// None is represented by the null value of MetaCall (i.e None in Python)
+impl<T: Into<Any>> From<Option<T>> for Any {
+    fn from(val: Option<T>) -> Self {
+        val.map_or(Any::Null, Into::into)
+    }
+}
+// Maps do not have a defined order, so the entries are built in iteration order
+impl<K: Into<Any>, V: Into<Any>> From<HashMap<K, V>> for Any {
+    fn from(val: HashMap<K, V>) -> Self {
+        Any::Map(val.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+impl<T: Into<Any>, const N: usize> From<[T; N]> for Any {
+    fn from(val: [T; N]) -> Self {
+        Any::from(Vec::from(val))
+    }
+}
+/// Collect values into an Any::Array, i.e for building an array argument:
+///
+/// ```no_run
+/// use metacall::Any;
+///
+/// let array: Any = (1..4).map(Any::from).collect();
+/// assert_eq!(array, Any::Array(vec![Any::Int(1), Any::Int(2), Any::Int(3)]));
+/// ```
+impl FromIterator<Any> for Any {
+    fn from_iter<I: IntoIterator<Item = Any>>(iter: I) -> Self {
+        Any::Array(iter.into_iter().collect())
+    }
+}
+
+// The widest integer supported by MetaCall is c_long (i64), so 128 bit integers can only be
+// converted when they are in the range of i64 (from i64::MIN to i64::MAX), otherwise they fail
+// instead of wrapping; this is also why From is not implemented for them
+impl TryFrom<i128> for Any {
+    type Error = Error;
+
+    fn try_from(val: i128) -> Result<Self, Self::Error> {
+        i64::try_from(val).map(Any::Long).map_err(|_| {
+            Error::new(
+                ErrorKind::ConversionFailed,
+                format!("Integer {} does not fit into a long (i64)", val),
+            )
+        })
+    }
+}
+impl TryFrom<u128> for Any {
+    type Error = Error;
+
+    fn try_from(val: u128) -> Result<Self, Self::Error> {
+        i64::try_from(val).map(Any::Long).map_err(|_| {
+            Error::new(
+                ErrorKind::ConversionFailed,
+                format!("Integer {} does not fit into a long (i64)", val),
+            )
+        })
+    }
+}
+impl TryFrom<u64> for Any {
+    type Error = Error;
+
+    fn try_from(val: u64) -> Result<Self, Self::Error> {
+        Any::try_from(val as u128)
+    }
+}
+impl TryFrom<usize> for Any {
+    type Error = Error;
+
+    fn try_from(val: usize) -> Result<Self, Self::Error> {
+        Any::try_from(val as u128)
+    }
+}
+
+fn conversion_error(val: &Any, target: &str) -> Error {
+    Error::new(
+        ErrorKind::ConversionFailed,
+        format!(
+            "Cannot convert {} {:?} into {}",
+            val.type_name(),
+            val,
+            target
+        ),
+    )
+}
+
+// Numeric conversions allow widening (i.e Any::Int into i64) and narrowing
+// only when the value fits into the target type, so they are never lossy
+impl TryFrom<Any> for i16 {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val {
+            Any::Short(x) => Ok(x),
+            Any::Int(x) => i16::try_from(x).map_err(|_| conversion_error(&val, "i16")),
+            Any::Long(x) => i16::try_from(x).map_err(|_| conversion_error(&val, "i16")),
+            _ => Err(conversion_error(&val, "i16")),
+        }
+    }
+}
+impl TryFrom<Any> for i32 {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val {
+            Any::Short(x) => Ok(x as i32),
+            Any::Int(x) => Ok(x),
+            Any::Long(x) => i32::try_from(x).map_err(|_| conversion_error(&val, "i32")),
+            _ => Err(conversion_error(&val, "i32")),
+        }
+    }
+}
+impl TryFrom<Any> for i64 {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val {
+            Any::Short(x) => Ok(x as i64),
+            Any::Int(x) => Ok(x as i64),
+            Any::Long(x) => Ok(x),
+            _ => Err(conversion_error(&val, "i64")),
+        }
+    }
+}
+impl TryFrom<Any> for i128 {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val.as_i64() {
+            Some(x) => Ok(x as i128),
+            None => Err(conversion_error(&val, "i128")),
+        }
+    }
+}
+impl TryFrom<Any> for u128 {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val.as_i64() {
+            Some(x) if x >= 0 => Ok(x as u128),
+            _ => Err(conversion_error(&val, "u128")),
+        }
+    }
+}
+impl TryFrom<Any> for f32 {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val {
+            Any::Float(x) => Ok(x),
+            _ => Err(conversion_error(&val, "f32")),
+        }
+    }
+}
+impl TryFrom<Any> for f64 {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val {
+            Any::Float(x) => Ok(x as f64),
+            Any::Double(x) => Ok(x),
+            _ => Err(conversion_error(&val, "f64")),
+        }
+    }
+}
+impl TryFrom<Any> for bool {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val {
+            Any::Bool(x) => Ok(x),
+            _ => Err(conversion_error(&val, "bool")),
+        }
+    }
+}
+impl TryFrom<Any> for char {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val {
+            Any::Char(x) => Ok(x),
+            _ => Err(conversion_error(&val, "char")),
+        }
+    }
+}
+impl TryFrom<Any> for String {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val {
+            Any::Str(x) => Ok(x),
+            _ => Err(conversion_error(&val, "String")),
+        }
+    }
+}
+impl TryFrom<Any> for Vec<u8> {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val {
+            Any::Buffer(x) => Ok(x),
+            _ => Err(conversion_error(&val, "Vec<u8>")),
+        }
+    }
+}
+impl TryFrom<Any> for Vec<Any> {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val {
+            Any::Array(x) => Ok(x),
+            _ => Err(conversion_error(&val, "Vec<Any>")),
+        }
+    }
+}
+impl TryFrom<Any> for HashMap<String, Any> {
+    type Error = Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        match val {
+            Any::Map(x) if x.iter().all(|(k, _)| matches!(k, Any::Str(_))) => Ok(x
+                .into_iter()
+                .filter_map(|(k, v)| match k {
+                    Any::Str(k) => Some((k, v)),
+                    _ => None,
+                })
+                .collect()),
+            _ => Err(conversion_error(&val, "HashMap<String, Any>")),
+        }
+    }
+}
+
+// Fixed size arrays (i.e Python `return x, y`) are converted into tuples of the
+// same size, where each element is converted with its own TryFrom implementation
+macro_rules! impl_try_from_any_tuple {
+    ($size:expr; $($name:ident),+) => {
+        impl<$($name),+> TryFrom<Any> for ($($name,)+)
+        where
+            $($name: TryFrom<Any>, $name::Error: fmt::Display),+
+        {
+            type Error = Error;
+
+            fn try_from(val: Any) -> Result<Self, Self::Error> {
+                let array = match val {
+                    Any::Array(x) if x.len() == $size => x,
+                    Any::Array(x) => {
+                        return Err(Error::new(ErrorKind::ConversionFailed, format!(
+                            "Cannot convert an array of {} elements into a tuple of {} elements",
+                            x.len(),
+                            $size
+                        )))
+                    }
+                    _ => return Err(conversion_error(&val, "tuple")),
+                };
+                let mut elements = array.into_iter().enumerate();
+                Ok(($({
+                    let (i, element) = elements.next().unwrap();
+                    $name::try_from(element).map_err(|e| {
+                        Error::new(ErrorKind::ConversionFailed, format!("Invalid element {} of the tuple: {}", i, e))
+                    })?
+                },)+))
+            }
+        }
+    };
+}
+
+impl_try_from_any_tuple!(1; A);
+impl_try_from_any_tuple!(2; A, B);
+impl_try_from_any_tuple!(3; A, B, C);
+impl_try_from_any_tuple!(4; A, B, C, D);
+impl_try_from_any_tuple!(5; A, B, C, D, E);
+impl_try_from_any_tuple!(6; A, B, C, D, E, F);
+
+/// Log the duration and the result of an operation (enabled with the log feature),
+/// when the feature is disabled it just runs the operation, so it has no cost
+#[cfg(feature = "log")]
+fn trace<T>(operation: &str, name: &str, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    let start = std::time::Instant::now();
+    let result = f();
+    match &result {
+        Ok(_) => log::debug!(
+            "{} '{}' succeeded in {:?}",
+            operation,
+            name,
+            start.elapsed()
+        ),
+        Err(e) => log::warn!(
+            "{} '{}' failed in {:?}: {}",
+            operation,
+            name,
+            start.elapsed(),
+            e
+        ),
+    }
+    result
+}
+
+#[cfg(not(feature = "log"))]
+#[inline(always)]
+fn trace<T>(_: &str, _: &str, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    f()
+}
+
+/// Convert a string into a C String, failing (instead of panicking) when it contains a null byte
+fn to_cstring(value: &str, what: &str) -> Result<CString, Error> {
+    CString::new(value).map_err(|_| null_byte_error(value, what))
+}
+
+/// Same as to_cstring but copying the string into buffer (which is cleared first), so the
+/// allocation of the buffer can be reused for many strings while each one is only borrowed
+fn to_cstr_in<'a>(value: &str, what: &str, buffer: &'a mut Vec<u8>) -> Result<&'a CStr, Error> {
+    buffer.clear();
+    buffer.extend_from_slice(value.as_bytes());
+    buffer.push(0);
+    CStr::from_bytes_with_nul(buffer).map_err(|_| null_byte_error(value, what))
+}
+
+/// Error of a call that did not return any value, functions returning null (i.e None
+/// in Python) return a null value, so no value at all means that the call failed
+fn call_failed_error(func: &str) -> Error {
+    Error::new(
+        ErrorKind::CallFailed,
+        format!(
+            "The call to '{}' failed without returning a value, see the output of the loader",
+            func
+        ),
+    )
+}
+
+fn null_byte_error(value: &str, what: &str) -> Error {
+    Error::new(
+        ErrorKind::ConversionFailed,
+        format!("Invalid {} {:?}: it contains a null byte", what, value),
+    )
+}
+
+/// Number of active initializations, MetaCall is only destroyed when it reaches zero; the
+/// lock is held while MetaCall is initialized or destroyed, so other threads initializing
+/// it at the same time wait until it is ready (or until it failed) instead of using it
+static INITIALIZED: Mutex<usize> = Mutex::new(0);
+
+/// Number of times MetaCall has been destroyed, it is only updated while holding the lock of
+/// INITIALIZED; values kept by Rust (i.e FunctionValue) are only valid in their generation
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Lock the initialization count, a panic while holding it (i.e in MetaCall) cannot leave
+/// the count inconsistent, since it is only updated after the call succeeds
+fn initialized() -> MutexGuard<'static, usize> {
+    INITIALIZED.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Guard returned by initialize, MetaCall is destroyed when it goes out of scope,
+/// it is not Send so it is destroyed in the same thread it was initialized
+#[must_use = "MetaCall is destroyed as soon as the guard is dropped"]
+pub struct MetacallGuard(PhantomData<*const ()>);
+
+impl Drop for MetacallGuard {
+    fn drop(&mut self) {
+        destroy();
+    }
+}
+
+/// Initialize MetaCall, the returned guard destroys it when dropped, so it
+/// must be kept alive (i.e `let _guard = metacall::initialize()?;`) while
+/// MetaCall is in use; use std::mem::forget on it for manual destroy
+///
+/// Initialization is reference counted, so it can be called multiple times (i.e by
+/// different libraries embedding MetaCall), each call must be paired with a destroy
+pub fn initialize() -> Result<MetacallGuard, Error> {
+    entry(|| initialize_with(&InitOptions::default()))
+}
+
+/// Flag of metacall_flags for supporting fork in the scripts (METACALL_FLAGS_FORK_SAFE)
+const METACALL_FLAGS_FORK_SAFE: c_int = 0x01;
+
+/// Configuration of MetaCall set by initialize_with, i.e for embedding it in an application
+/// which ships its own plugins instead of using the installed ones:
+///
+/// ```ignore
+/// let options = metacall::InitOptions::new()
+///     .env("LOADER_LIBRARY_PATH", "/opt/app/metacall")
+///     .execution_path("py", "/opt/app/scripts");
+/// let _guard = metacall::initialize_with(&options)?;
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct InitOptions {
+    env: Vec<(String, std::ffi::OsString)>,
+    execution_paths: Vec<(String, String)>,
+    fork_safe: bool,
+}
+
+impl InitOptions {
+    /// Create the default configuration (the same used by initialize)
+    pub fn new() -> InitOptions {
+        InitOptions::default()
+    }
+
+    /// Set an environment variable read by MetaCall while initializing, i.e the paths of its
+    /// plugins (LOADER_LIBRARY_PATH, SERIAL_LIBRARY_PATH, DETOUR_LIBRARY_PATH), its configuration
+    /// (CONFIGURATION_PATH), the scripts (LOADER_SCRIPT_PATH) or the runtimes (i.e PYTHONHOME);
+    /// MetaCall only reads them from the environment, so they are set for the whole process
+    pub fn env(mut self, var: &str, value: impl Into<std::ffi::OsString>) -> InitOptions {
+        self.env.push((var.to_string(), value.into()));
+        self
+    }
+
+    /// Add a path where the loader of the given tag looks for the scripts to be loaded
+    pub fn execution_path(mut self, tag: &str, path: &str) -> InitOptions {
+        self.execution_paths
+            .push((tag.to_string(), path.to_string()));
+        self
+    }
+
+    /// Support forking the process from the scripts (i.e Python multiprocessing)
+    pub fn fork_safe(mut self, fork_safe: bool) -> InitOptions {
+        self.fork_safe = fork_safe;
+        self
+    }
+}
+
+/// Same as initialize but with the given configuration, the environment and the flags only
+/// take effect when MetaCall is initialized for the first time (it is reference counted, see
+/// initialize), while the execution paths are added on each call; loaders are loaded lazily
+/// when their first script is loaded, so they do not need to be enabled beforehand
+pub fn initialize_with(options: &InitOptions) -> Result<MetacallGuard, Error> {
+    entry(|| {
+        {
+            let mut count = initialized();
+            if *count == 0 {
+                for (var, value) in &options.env {
+                    std::env::set_var(var, value);
+                }
+                if options.fork_safe {
+                    // SAFETY: the flags are read by metacall_initialize, so they must be set before it
+                    unsafe { metacall_flags(METACALL_FLAGS_FORK_SAFE) };
+                }
+                if unsafe { metacall_initialize() } != 0 {
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        format!(
+                            "MetaCall failed to initialize, {}",
+                            initialize_error_reason(|var| std::env::var_os(var))
+                        ),
+                    ));
+                }
+            }
+            *count += 1;
+        }
+        // The guard destroys MetaCall if an execution path fails, so it must
+        // be created after releasing the lock, which destroy takes again
+        let guard = MetacallGuard(PhantomData);
+        for (tag, path) in &options.execution_paths {
+            let c_tag = to_cstring(tag, "tag")?;
+            let c_path = to_cstring(path, "execution path")?;
+            // SAFETY: c_tag and c_path live until the end of the iteration, MetaCall copies them
+            if unsafe { metacall_execution_path(c_tag.as_ptr(), c_path.as_ptr()) } != 0 {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!(
+                        "MetaCall failed to add the execution path '{}' to the loader '{}'",
+                        path, tag
+                    ),
+                ));
+            }
+        }
+        Ok(guard)
+    })
+}
+
+/// Environment variables with the paths where MetaCall looks for its plugins and configuration
+const PLUGIN_PATH_VARS: [&str; 4] = [
+    "SERIAL_LIBRARY_PATH",
+    "DETOUR_LIBRARY_PATH",
+    "CONFIGURATION_PATH",
+    "LOADER_LIBRARY_PATH",
+];
+
+/// Guess why the initialization failed, MetaCall only prints the reason in its log, but the
+/// usual cause is that it cannot find its plugins (i.e the serializer needed for reading the
+/// configuration) because they are not installed in the default paths (env reads a variable)
+fn initialize_error_reason(env: impl Fn(&str) -> Option<std::ffi::OsString>) -> String {
+    let invalid: Vec<String> = PLUGIN_PATH_VARS
+        .iter()
+        .filter_map(|var| match env(var) {
+            Some(path) if !std::path::Path::new(&path).exists() => {
+                Some(format!("{}={:?}", var, path))
+            }
+            _ => None,
+        })
+        .collect();
+    if !invalid.is_empty() {
+        return format!("these paths do not exist: {}", invalid.join(", "));
+    }
+    let unset: Vec<&str> = PLUGIN_PATH_VARS
+        .iter()
+        .copied()
+        .filter(|var| env(var).is_none())
+        .collect();
+    if unset.is_empty() {
+        return "see the output of MetaCall for details".to_string();
+    }
+    format!(
+        "its plugins may not be installed in the default paths, try setting {}",
+        unset.join(", ")
+    )
+}
+
+/// Check if MetaCall has been initialized and not destroyed yet
+pub fn is_initialized() -> bool {
+    *initialized() > 0
+}
+
+/// Check if the loader of the given tag (i.e "py" or "node") can be used, either because
+/// it is already initialized or because its plugin (i.e libpy_loader.so) is installed
+///
+/// MetaCall loads each loader lazily when the first script of its tag is loaded, so the plugin
+/// is looked up where MetaCall loads it from: LOADER_LIBRARY_PATH if it is set, otherwise the
+/// directory of libmetacall, which is where the plugins are installed by default (the path
+/// MetaCall was built with is not exposed); an installed plugin may still fail to initialize
+/// (i.e if its runtime is missing), the load functions return LoaderNotFound in that case
+pub fn loader_available(tag: &str) -> bool {
+    loader_initialized(tag)
+        || loader_library_dir().is_some_and(|dir| find_loader_library(tag, &dir).is_some())
+}
+
+/// Check if the loader of the given tag has been loaded and initialized
+fn loader_initialized(tag: &str) -> bool {
+    match to_cstring(tag, "tag") {
+        // SAFETY: c_tag is bound to the match arm, so it outlives the call
+        Ok(c_tag) => unsafe { metacall_is_initialized(c_tag.as_ptr()) == 0 },
+        Err(_) => false,
+    }
+}
+
+/// Directory where MetaCall looks for the loader plugins, see loader_available
+fn loader_library_dir() -> Option<PathBuf> {
+    match std::env::var_os("LOADER_LIBRARY_PATH") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => metacall_library_dir(),
+    }
+}
+
+/// Directory of the libmetacall linked into the process
+#[cfg(unix)]
+fn metacall_library_dir() -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut info = DlInfo {
+        dli_fname: std::ptr::null(),
+        dli_fbase: std::ptr::null_mut(),
+        dli_sname: std::ptr::null(),
+        dli_saddr: std::ptr::null_mut(),
+    };
+    // SAFETY: dladdr only fills info, whose file name is owned by the dynamic linker
+    unsafe {
+        if dladdr(metacall_initialize as *const c_void, &mut info) == 0 || info.dli_fname.is_null()
+        {
+            return None;
+        }
+        let path = std::ffi::OsStr::from_bytes(CStr::from_ptr(info.dli_fname).to_bytes());
+        Path::new(path).parent().map(Path::to_path_buf)
+    }
+}
+
+#[cfg(not(unix))]
+fn metacall_library_dir() -> Option<PathBuf> {
+    None
+}
+
+/// Path of the plugin of the loader in dir, MetaCall loads the plugins with a d suffix
+/// (i.e libpy_loaderd.so) when it is built in debug mode, so both names are checked
+fn find_loader_library(tag: &str, dir: &Path) -> Option<PathBuf> {
+    ["_loader", "_loaderd"]
+        .iter()
+        .map(|suffix| {
+            dir.join(format!(
+                "{}{}{}{}",
+                std::env::consts::DLL_PREFIX,
+                tag,
+                suffix,
+                std::env::consts::DLL_SUFFIX
+            ))
+        })
+        .find(|path| path.is_file())
+}
+
+/// Handle of a module loaded with load_from_file_handle, its functions
+/// are not available in the global scope, only through metacall_handle
+/// (like Function, it is bound to the thread that owns MetaCall)
+#[derive(Debug)]
+pub struct Handle(*mut c_void);
+
+fn load_from_file_impl(
+    tag: &str,
+    scripts: impl IntoIterator<Item = impl AsRef<str>>,
+    handle: *mut *mut c_void,
+) -> Result<(), Error> {
+    trace("Load from file", tag, || {
+        let ctag = to_cstring(tag, "tag")?;
+        let scripts: Vec<String> = scripts
+            .into_iter()
+            .map(|x| x.as_ref().to_string())
+            .collect();
+        let owned_scripts: Vec<_> = scripts
+            .iter()
+            .map(|x| to_cstring(x, "script path"))
+            .collect::<Result<_, _>>()?;
+        // The pointers borrow from owned_scripts, which must not be dropped until the call ends
+        let mut ref_c_scripts: Vec<_> = owned_scripts
+            .iter()
+            .map(|s| s.as_ptr())
+            .map(|p| p as *const u8)
+            .collect();
+        // SAFETY: ctag and owned_scripts (pointed by ref_c_scripts) live until the end of the
+        // closure, and MetaCall copies the paths, so they are not used after the call
+        if unsafe {
+            metacall_load_from_file(
+                ctag.as_ptr(),
+                ref_c_scripts.as_mut_ptr(),
+                ref_c_scripts.len(),
+                handle,
+            )
+        } != 0
+        {
+            return Err(Error::new(
+                load_error_kind(tag),
+                format!(
+                    "MetaCall failed to load script from file {:?} with loader '{}' ({})",
+                    scripts,
+                    tag,
+                    load_error_reason(tag, &scripts)
+                ),
+            ));
+        }
+        Ok(())
+    })
+}
+
+/// Kind of the error of a failed load, the loaders are initialized by their first load, so a
+/// loader which is still not initialized after a failed load could not be loaded at all
+fn load_error_kind(tag: &str) -> ErrorKind {
+    if loader_initialized(tag) {
+        ErrorKind::LoadFailed
+    } else {
+        ErrorKind::LoaderNotFound
+    }
+}
+
+/// Guess why a load failed, MetaCall does not report the errors of the loaders (they are
+/// printed by the loader itself, i.e Python prints the exception with its file and line),
+/// so only the common mistakes can be detected: unavailable loaders and missing scripts
+fn load_error_reason(tag: &str, scripts: &[String]) -> String {
+    if !loader_initialized(tag) {
+        return format!("the loader '{}' is not available", tag);
+    }
+    // Relative paths may be found in the execution paths of the loader, so this is not conclusive
+    match scripts.iter().find(|s| !std::path::Path::new(s).exists()) {
+        Some(script) => format!(
+            "'{}' does not exist relative to the working directory",
+            script
+        ),
+        None => "see the output of the loader for details".to_string(),
+    }
+}
+
+/// Load the scripts into the global scope, so their functions can be called by name
+pub fn load_from_file(
+    tag: &str,
+    scripts: impl IntoIterator<Item = impl AsRef<str>>,
+) -> Result<(), Error> {
+    entry(|| load_from_file_impl(tag, scripts, std::ptr::null_mut()))
+}
+
+/// Load the scripts into their own scope instead of the global one, the returned handle
+/// allows calling their functions even if other scripts define functions with the same name
+pub fn load_from_file_handle(
+    tag: &str,
+    scripts: impl IntoIterator<Item = impl AsRef<str>>,
+) -> Result<Handle, Error> {
+    entry(|| {
+        let mut handle: *mut c_void = std::ptr::null_mut();
+        load_from_file_impl(tag, scripts, &mut handle)?;
+        Ok(Handle(handle))
+    })
+}
+
+/// Builder for loading scripts of different loaders one by one, so if one of them
+/// fails, the error reports which one; the scripts loaded before a failure stay loaded
+#[derive(Debug, Default)]
+pub struct Loader {
+    scripts: Vec<(String, String)>,
+}
+
+impl Loader {
+    /// Create a loader without scripts
+    pub fn new() -> Loader {
+        Loader::default()
+    }
+
+    /// Add a script to be loaded with the loader of the given tag
+    pub fn file(mut self, tag: &str, path: &str) -> Loader {
+        self.scripts.push((tag.to_string(), path.to_string()));
+        self
+    }
+
+    /// Load the scripts into the global scope in the same order they were added
+    pub fn load(&self) -> Result<(), Error> {
+        entry(|| {
+            for (tag, path) in &self.scripts {
+                load_from_file(tag, [path])?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Load each script into its own scope, returning their handles in the same order
+    pub fn load_handles(&self) -> Result<Vec<Handle>, Error> {
+        entry(|| {
+            self.scripts
+                .iter()
+                .map(|(tag, path)| load_from_file_handle(tag, [path]))
+                .collect()
+        })
+    }
+}
+
+/// Names of the functions exported by a module loaded with load_from_file_handle,
+/// sorted alphabetically (other exported values, like classes, are not included)
+pub fn handle_functions(handle: &Handle) -> Result<Vec<String>, Error> {
+    entry(|| {
+        unsafe {
+            // The export is a map of names to copies of the values, owned by the caller
+            let exports = metacall_handle_export(handle.0);
+            if exports.is_null() {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    "MetaCall failed to export the handle",
+                ));
+            }
+            let tuples = metacall_value_to_map(exports);
+            let mut names = Vec::new();
+            for i in 0..metacall_value_count(exports) {
+                // Entries that failed to be exported are left empty
+                if (*tuples.add(i)).is_null() {
+                    continue;
+                }
+                let tuple = metacall_value_to_array(*tuples.add(i));
+                let id = MetacallValueId::from_raw(metacall_value_id(*tuple.add(1)));
+                if id == Some(MetacallValueId::Function) {
+                    if let Any::Str(name) = metacall_to_any(*tuple) {
+                        names.push(name);
+                    }
+                }
+            }
+            metacall_value_destroy(exports);
+            names.sort();
+            Ok(names)
+        }
+    })
+}
+
+/// Unload a module and its resources (i.e for reloading it), the handle is consumed
+/// so its functions cannot be called anymore
+pub fn clear(handle: Handle) -> Result<(), Error> {
+    entry(|| {
+        if unsafe { metacall_clear(handle.0) } != 0 {
+            return Err(Error::new(
+                ErrorKind::Other,
+                "MetaCall failed to clear the handle",
+            ));
+        }
+        Ok(())
+    })
+}
+
+/// Load a script from a string, the script is copied so it does not need to be owned
+pub fn load_from_memory(tag: &str, script: &str) -> Result<(), Error> {
+    entry(|| {
+        trace("Load from memory", tag, || {
+            load_from_cstr(tag, &to_cstring(script, "script")?, std::ptr::null_mut())
+        })
+    })
+}
+
+/// Load a script from bytes (i.e embedded with include_bytes!), they are not checked to be
+/// UTF-8 because each loader decodes its own scripts; if they already end with a null byte
+/// (i.e `b"print(1)\0"`) they are passed as they are, otherwise they are copied to append it
+pub fn load_from_bytes(tag: &str, script: &[u8]) -> Result<(), Error> {
+    entry(|| {
+        trace("Load from memory", tag, || {
+            let owned;
+            let cscript = match CStr::from_bytes_with_nul(script) {
+                Ok(cscript) => cscript,
+                Err(_) => {
+                    owned = CString::new(script).map_err(|e| {
+                        Error::new(
+                            ErrorKind::ConversionFailed,
+                            format!(
+                                "Invalid script: it contains a null byte at position {}",
+                                e.nul_position()
+                            ),
+                        )
+                    })?;
+                    owned.as_c_str()
+                }
+            };
+            load_from_cstr(tag, cscript, std::ptr::null_mut())
+        })
+    })
+}
+
+/// Load the script into the global scope, or into its own scope if handle is not null
+fn load_from_cstr(tag: &str, cscript: &CStr, handle: *mut *mut c_void) -> Result<(), Error> {
+    let ctag = to_cstring(tag, "tag")?;
+    // The size must include the null terminator of the script
+    let size = cscript.to_bytes_with_nul().len();
+    // SAFETY: ctag and cscript outlive the call, MetaCall copies the script
+    if unsafe { metacall_load_from_memory(ctag.as_ptr(), cscript.as_ptr(), size, handle) } != 0 {
+        return Err(Error::new(
+            load_error_kind(tag),
+            format!(
+                "MetaCall failed to load script from memory with loader '{}'",
+                tag
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Load a compiled or packaged module (i.e a shared library or an assembly)
+pub fn load_from_package(tag: &str, path: &str) -> Result<(), Error> {
+    entry(|| {
+        trace("Load from package", path, || {
+            let ctag = to_cstring(tag, "tag")?;
+            let cpath = to_cstring(path, "package path")?;
+            // SAFETY: ctag and cpath live until the end of the closure
+            if unsafe {
+                metacall_load_from_package(ctag.as_ptr(), cpath.as_ptr(), std::ptr::null_mut())
+            } != 0
+            {
+                return Err(Error::new(
+                    load_error_kind(tag),
+                    format!(
+                        "MetaCall failed to load package '{}' with loader '{}'",
+                        path, tag
+                    ),
+                ));
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Load all the scripts described by a MetaCall configuration file (i.e metacall.json)
+pub fn load_from_configuration(path: &str) -> Result<(), Error> {
+    entry(|| {
+        trace("Load from configuration", path, || {
+            let cpath = to_cstring(path, "configuration path")?;
+            let allocator = Allocator::new()?;
+            // SAFETY: cpath and the allocator live until the end of the closure
+            if unsafe {
+                metacall_load_from_configuration(cpath.as_ptr(), std::ptr::null_mut(), allocator.0)
+            } != 0
+            {
+                return Err(Error::new(ErrorKind::LoadFailed, format!(
+                "MetaCall failed to load configuration '{}' (invalid configuration or failed to load its scripts)",
+                path
+            )));
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Create a MetaCall value from an Any, the returned value is owned by the caller
+/// Compound values (arrays and maps) take ownership of their elements, so destroying
+/// the outer value with metacall_value_destroy also frees the inner ones
+unsafe fn any_to_metacall(arg: &Any) -> Result<*mut c_void, Error> {
+    any_to_metacall_in(arg, &mut Vec::new())
+}
+
+/// Same as any_to_metacall but using strings as the buffer for null terminating the strings,
+/// MetaCall copies them when creating the values, so it is reused for all the strings of a
+/// batch of values (i.e the arguments of many calls) instead of allocating one for each
+unsafe fn any_to_metacall_in(arg: &Any, strings: &mut Vec<u8>) -> Result<*mut c_void, Error> {
+    let value = match arg {
+        Any::Short(x) => metacall_value_create_short(*x),
+        Any::Int(x) => metacall_value_create_int(*x),
+        Any::Long(x) => metacall_value_create_long(*x),
+        Any::Float(x) => metacall_value_create_float(*x),
+        Any::Double(x) => metacall_value_create_double(*x),
+        Any::Bool(x) => metacall_value_create_bool(*x as c_uchar),
+        // See value_id, chars outside of ASCII are passed as strings
+        Any::Char(x) if arg.value_id() == MetacallValueId::Char => {
+            metacall_value_create_char(*x as c_char)
+        }
+        Any::Char(x) => {
+            // The string is copied including its null terminator, so there is room for it
+            let mut st = [0u8; 5];
+            let len = x.encode_utf8(&mut st).len();
+            // SAFETY: st lives until the end of the arm and MetaCall copies it
+            metacall_value_create_string(st.as_ptr() as *const c_char, len)
+        }
+        Any::Str(x) => {
+            // SAFETY: st borrows strings until the end of the arm, MetaCall copies the string
+            // (of x.len() bytes plus the null terminator, which to_cstr_in appends)
+            let st = to_cstr_in(x, "string", strings)?;
+            metacall_value_create_string(st.as_ptr(), x.len())
+        }
+        // SAFETY: the buffer is borrowed from arg, which outlives the call, and it is copied
+        Any::Buffer(x) => metacall_value_create_buffer(x.as_ptr() as *const c_void, x.len()),
+        Any::Array(x) => {
+            let values = any_to_metacall_list_in(x, strings)?;
+            // SAFETY: the array copies the pointers and takes ownership of the values,
+            // so dropping the vector (not the values) after the call is correct
+            metacall_value_create_array(values.as_ptr() as *const *const c_void, values.len())
+        }
+        Any::Map(x) => {
+            // Each entry of the map is a tuple (an array of two elements: key and value)
+            let mut tuples: Vec<*mut c_void> = Vec::with_capacity(x.len());
+            for (k, v) in x {
+                match any_to_metacall_list_in([k, v], strings) {
+                    Ok(pair) => tuples.push(metacall_value_create_array(
+                        pair.as_ptr() as *const *const c_void,
+                        pair.len(),
+                    )),
+                    Err(e) => {
+                        destroy_values(tuples);
+                        return Err(e);
+                    }
+                }
+            }
+            // SAFETY: same as arrays, the map takes ownership of the tuples
+            metacall_value_create_map(tuples.as_ptr() as *const *const c_void, tuples.len())
+        }
+        Any::Pointer(x) => metacall_value_create_ptr(x.0),
+        Any::Function(f) => {
+            // Register an anonymous function whose closure is the Rust function pointer,
+            // the value holds a reference to it so it lives as long as the script keeps it;
+            // nothing is allocated by Rust (function pointers are 'static), so once the last
+            // value is destroyed the function is released and nothing is left behind
+            let mut func: *mut c_void = std::ptr::null_mut();
+            let types = [MetacallValueId::Invalid];
+            if metacall_registerv(
+                std::ptr::null(),
+                function_trampoline,
+                &mut func,
+                MetacallValueId::Invalid,
+                types.len(),
+                types.as_ptr(),
+            ) != 0
+            {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    "Failed to register the function callback",
+                ));
+            }
+            metacall_value_create_function_closure(func, **f as *mut c_void)
+        }
+        // SAFETY: the copy holds its own reference to the function, owned by the caller
+        Any::FunctionValue(x) => {
+            x.0.alive()?;
+            metacall_value_copy(x.0.value)
+        }
+        Any::Null => metacall_value_create_null(),
+    };
+    Ok(value)
+}
+
+/// Create a MetaCall value for each Any, if any of them fails the
+/// values created until then are destroyed, so nothing is leaked
+unsafe fn any_to_metacall_list(
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<Vec<*mut c_void>, Error> {
+    any_to_metacall_list_in(args, &mut Vec::new())
+}
+
+/// Same as any_to_metacall_list reusing the buffer of the strings (see any_to_metacall_in)
+unsafe fn any_to_metacall_list_in(
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+    strings: &mut Vec<u8>,
+) -> Result<Vec<*mut c_void>, Error> {
+    let mut values = Vec::new();
+    for arg in args {
+        match any_to_metacall_in(arg.borrow(), strings) {
+            Ok(value) => values.push(value),
+            Err(e) => {
+                destroy_values(values);
+                return Err(e);
+            }
+        }
+    }
+    Ok(values)
+}
+
+unsafe fn destroy_values(values: impl IntoIterator<Item = *mut c_void>) {
+    for value in values {
+        metacall_value_destroy(value);
+    }
+}
+
+/// Pass the arguments to f as an array and its size, the array must be valid even if it is
+/// empty (an empty vector does not allocate and its pointer is dangling), so it is terminated
+/// by a null pointer (like metacall_null_args does in C), which is removed after the call;
+/// MetaCall may replace the arguments in the array, so they are read back from c_args
+fn with_args_array<R>(
+    c_args: &mut Vec<*mut c_void>,
+    f: impl FnOnce(*mut *mut c_void, usize) -> R,
+) -> R {
+    let size = c_args.len();
+    c_args.push(std::ptr::null_mut());
+    let ret = f(c_args.as_mut_ptr(), size);
+    c_args.pop();
+    ret
+}
+
+/// Entry point for scripts calling a Rust function passed as Any::Function,
+/// the closure data is the function pointer itself (which is 'static)
+///
+/// The function takes a single argument, which is Null if the script passes none; calls
+/// with more than one argument fail (the script receives no value) instead of dropping them
+extern "C" fn function_trampoline(
+    argc: usize,
+    argv: *mut *mut c_void,
+    data: *mut c_void,
+) -> *mut c_void {
+    unsafe {
+        let f: fn(Any) -> Any = std::mem::transmute(data);
+        if argc > 1 {
+            return callback_return(Err(Error::new(
+                ErrorKind::CallFailed,
+                format!("Rust function expects one argument, got {}", argc),
+            )));
+        }
+        // Arguments are owned by the caller, the returned value is owned by MetaCall
+        let arg = if argc > 0 && !(*argv).is_null() {
+            metacall_to_any(*argv)
+        } else {
+            Any::Null
+        };
+        callback_return(callback_catch(|| Ok(f(arg))))
+    }
+}
+
+/// Convert a MetaCall value into an Any, the value is only borrowed so
+/// it must still be destroyed by the caller (if it owns it)
+unsafe fn metacall_to_any(ret: *mut c_void) -> Any {
+    match MetacallValueId::from_raw(metacall_value_id(ret)) {
+        // Any non zero boolean is true, loaders only create booleans from their own boolean
+        // type (i.e Python True and False), so truthy integers are not coerced into bools
+        Some(MetacallValueId::Bool) => Any::Bool(metacall_value_to_bool(ret) != 0),
+        Some(MetacallValueId::Char) => Any::from(metacall_value_to_char(ret)),
+        Some(MetacallValueId::Short) => Any::Short(metacall_value_to_short(ret)),
+        Some(MetacallValueId::Int) => Any::Int(metacall_value_to_int(ret)),
+        Some(MetacallValueId::Long) => Any::Long(metacall_value_to_long(ret)),
+        Some(MetacallValueId::Float) => Any::Float(metacall_value_to_float(ret)),
+        Some(MetacallValueId::Double) => Any::Double(metacall_value_to_double(ret)),
+        Some(MetacallValueId::String) => {
+            // Strings which are not valid UTF-8 are returned as raw bytes, so no data is lost
+            let st = std::ffi::CStr::from_ptr(metacall_value_to_string(ret));
+            match st.to_str() {
+                Ok(st) => Any::Str(String::from(st)),
+                Err(_) => Any::Buffer(st.to_bytes().to_vec()),
+            }
+        }
+        Some(MetacallValueId::Buffer) => {
+            // The buffer is raw binary data (it may contain null bytes), so copy it by size
+            let buffer = metacall_value_to_buffer(ret) as *const u8;
+            let size = metacall_value_size(ret);
+            Any::Buffer(std::slice::from_raw_parts(buffer, size).to_vec())
+        }
+        Some(MetacallValueId::Array) => {
+            // The elements are owned by the array, so they must not be destroyed here
+            let values = metacall_value_to_array(ret);
+            let count = metacall_value_count(ret);
+            Any::Array(
+                (0..count)
+                    .map(|i| metacall_to_any(*values.add(i)))
+                    .collect(),
+            )
+        }
+        Some(MetacallValueId::Map) => {
+            // The tuples are arrays of two elements (key and value) owned by the map, entries
+            // left empty by the loader (like in handle_functions) or incomplete are skipped
+            let tuples = metacall_value_to_map(ret);
+            let count = metacall_value_count(ret);
+            Any::Map(
+                (0..count)
+                    .map(|i| *tuples.add(i))
+                    .filter(|tuple| !tuple.is_null() && metacall_value_count(*tuple) >= 2)
+                    .map(|tuple| {
+                        let pair = metacall_value_to_array(tuple);
+                        (metacall_to_any(*pair), metacall_to_any(*pair.add(1)))
+                    })
+                    .collect(),
+            )
+        }
+        Some(MetacallValueId::Ptr) => Any::Pointer(OpaquePointer(metacall_value_to_ptr(ret))),
+        Some(MetacallValueId::Future) => {
+            // Futures can only be awaited through metacall_await
+            Any::Null
+        }
+        Some(MetacallValueId::Function) => {
+            // The value is borrowed, so keep a copy (which references the same function)
+            Any::FunctionValue(FunctionValue(Arc::new(FunctionValueRef::new(ret))))
+        }
+        _ => Any::Null,
+    }
+}
+
+/// Call a function by name with the given arguments
+///
+/// The arguments can be borrowed (i.e `&[Any::Int(1)]`) or owned (i.e `vec![Any::Int(1)]`),
+/// an empty list must be borrowed (`&[]`), otherwise the type of its elements cannot be inferred
+///
+/// Exceptions thrown by the script are not reported through the result, this version
+/// of MetaCall has no throwable value type, the loaders print them to stderr and the
+/// call returns no value at all, which is returned as an error (while functions that
+/// return null, i.e None in Python, return Any::Null); some loaders also return no
+/// value for functions without return value (i.e void methods in C#), so their calls
+/// fail in the same way even if they succeeded
+pub fn metacall(
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<Any, Error> {
+    entry(|| trace("Call", func, || function(func)?.call(args)))
+}
+
+/// Function resolved by name only once, so it can be called repeatedly (i.e in a tight
+/// loop) without looking it up on each call, it is valid while its script stays loaded
+#[derive(Debug)]
+pub struct Function(*mut c_void, String);
+
+impl Function {
+    /// Call the function with the given arguments
+    pub fn call(&self, args: impl IntoIterator<Item = impl Borrow<Any>>) -> Result<Any, Error> {
+        entry(|| unsafe { call_function(self.0, &self.1, args) })
+    }
+
+    /// Name the function was resolved with
+    pub fn name(&self) -> &str {
+        &self.1
+    }
+
+    /// Call the function failing before invoking it if the number of arguments does not
+    /// match the number of parameters declared (it must not be used with variadic functions)
+    pub fn call_strict(
+        &self,
+        args: impl IntoIterator<Item = impl Borrow<Any>>,
+    ) -> Result<Any, Error> {
+        entry(|| {
+            let args: Vec<_> = args.into_iter().collect();
+            let size = self.size();
+            if args.len() != size {
+                return Err(Error::new(
+                    ErrorKind::CallFailed,
+                    format!("Expected {} arguments, got {}", size, args.len()),
+                ));
+            }
+            self.call(args)
+        })
+    }
+
+    /// Number of parameters declared by the function
+    pub fn size(&self) -> usize {
+        unsafe { metacall_function_size(self.0) }
+    }
+
+    /// Whether the function is declared as asynchronous by its script (the same flag as
+    /// InspectFunction::is_async), only asynchronous functions can be awaited
+    pub fn is_async(&self) -> bool {
+        unsafe { metacall_function_async(self.0) == 1 }
+    }
+
+    /// Type of each parameter declared by the function, it is None when the
+    /// type is not known (i.e parameters of dynamically typed languages)
+    pub fn arg_types(&self) -> Vec<Option<MetacallValueId>> {
+        (0..self.size())
+            .map(|i| {
+                let mut id: c_int = MetacallValueId::Invalid as c_int;
+                if unsafe { metacall_function_parameter_type(self.0, i, &mut id) } != 0 {
+                    return None;
+                }
+                MetacallValueId::from_raw(id)
+            })
+            .collect()
+    }
+}
+
+/// Call a function value returned by a script (see FunctionValue), it fails if the value
+/// is not a function, Rust functions (Any::Function) are called directly instead
+pub fn call_value(
+    func_value: &Any,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<Any, Error> {
+    entry(|| {
+        match func_value {
+            // SAFETY: the function is kept alive by the value during the call
+            Any::FunctionValue(x) => {
+                x.0.alive()?;
+                // Function values are anonymous, so they are named as they are displayed
+                unsafe { call_function(x.0.function, "<function>", args) }
+            }
+            _ => Err(Error::new(
+                ErrorKind::CallFailed,
+                format!(
+                    "Cannot call {} {:?}, it is not a function value",
+                    func_value.type_name(),
+                    func_value
+                ),
+            )),
+        }
+    })
+}
+
+/// Resolve a function by name from the global scope
+pub fn function(name: &str) -> Result<Function, Error> {
+    entry(|| {
+        let c_function = to_cstring(name, "function name")?;
+        // SAFETY: c_function lives until the end of the function
+        let c_func: *mut c_void = unsafe { metacall_function(c_function.as_ptr()) };
+        if c_func.is_null() {
+            return Err(Error::new(
+                ErrorKind::FunctionNotFound,
+                format!("Function '{}' not found", name),
+            ));
+        }
+        Ok(Function(c_func, name.to_string()))
+    })
+}
+
+/// Same as metacall but checking the number of arguments first (see Function::call_strict)
+pub fn metacall_strict(
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<Any, Error> {
+    entry(|| function(func)?.call_strict(args))
+}
+
+/// Call a function by name loading the script first if the function is not found
+/// (i.e for scripts loaded on demand), if the script does not define it, it fails
+pub fn call_or_load(
+    tag: &str,
+    script: &str,
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<Any, Error> {
+    entry(|| {
+        // Only a missing function means that the script is not loaded yet, the rest of
+        // errors (i.e a function name with a null byte) are returned as they are
+        let function = match function(func) {
+            Ok(function) => function,
+            Err(e) if e.kind() == ErrorKind::FunctionNotFound => {
+                load_from_file(tag, [script])?;
+                function(func).map_err(|e| match e.kind() {
+                    ErrorKind::FunctionNotFound => Error::new(
+                        ErrorKind::FunctionNotFound,
+                        format!(
+                            "Function '{}' not found after loading script '{}'",
+                            func, script
+                        ),
+                    ),
+                    _ => e,
+                })?
+            }
+            Err(e) => return Err(e),
+        };
+        function.call(args)
+    })
+}
+
+/// Call a function with positional and named arguments, the names are mapped into the
+/// position of the parameter with the same name, so all the parameters must be passed
+///
+/// MetaCall does not support variadic keyword arguments (i.e Python `**kwargs`), in order
+/// to call those functions, pass the named arguments as a map (i.e `Any::from(kwargs)`),
+/// which is received as a dictionary by the script
+pub fn metacall_kw(
+    func: &str,
+    positional: &[Any],
+    kwargs: HashMap<String, Any>,
+) -> Result<Any, Error> {
+    entry(|| {
+        let function = function(func)?;
+        let size = function.size();
+        if positional.len() + kwargs.len() != size {
+            return Err(Error::new(
+                ErrorKind::CallFailed,
+                format!(
+                    "Function '{}' expected {} arguments, got {}",
+                    func,
+                    size,
+                    positional.len() + kwargs.len()
+                ),
+            ));
+        }
+        // Unknown names make MetaCall fail without reporting it, so they are checked before
+        if !kwargs.is_empty() {
+            let names = function_param_names(func)?;
+            if let Some(name) = kwargs.keys().find(|name| !names.contains(name)) {
+                return Err(Error::new(
+                    ErrorKind::CallFailed,
+                    format!(
+                        "Function '{}' does not have a parameter named '{}'",
+                        func, name
+                    ),
+                ));
+            }
+        }
+        let (names, values): (Vec<Any>, Vec<Any>) =
+            kwargs.into_iter().map(|(k, v)| (Any::Str(k), v)).unzip();
+        let keys: Vec<Any> = (0..positional.len() as i32)
+            .map(Any::Int)
+            .chain(names)
+            .collect();
+        unsafe {
+            let mut c_keys = any_to_metacall_list(&keys)?;
+            let mut c_values = match any_to_metacall_list(positional.iter().chain(&values)) {
+                Ok(c_values) => c_values,
+                Err(e) => {
+                    destroy_values(c_keys);
+                    return Err(e);
+                }
+            };
+            // Keys and values may be replaced by MetaCall when they are casted
+            // SAFETY: both vectors have exactly size elements (checked above) and live until
+            // they are destroyed, if they are empty MetaCall does not read from them
+            let ret = metacallfmv(function.0, c_keys.as_mut_ptr(), c_values.as_mut_ptr());
+            destroy_values(c_keys);
+            destroy_values(c_values);
+            // See call_function_with, no value at all means that the call failed
+            if ret.is_null() {
+                return Err(call_failed_error(func));
+            }
+            let rt = metacall_to_any(ret);
+            metacall_value_destroy(ret);
+            Ok(rt)
+        }
+    })
+}
+
+/// Number of parameters declared by the function with the given name
+pub fn function_size(func: &str) -> Result<usize, Error> {
+    entry(|| Ok(function(func)?.size()))
+}
+
+/// Type of each parameter declared by the function with the given name (see Function::arg_types)
+pub fn function_arg_types(func: &str) -> Result<Vec<Option<MetacallValueId>>, Error> {
+    entry(|| Ok(function(func)?.arg_types()))
+}
+
+/// Call a function by name within the scope of a module loaded with load_from_file_handle
+pub fn metacall_handle(
+    handle: &Handle,
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<Any, Error> {
+    entry(|| {
+        trace("Call", func, || {
+            let c_function = to_cstring(func, "function name")?;
+            // SAFETY: c_function lives until the end of the closure and the handle
+            // is borrowed, so it cannot be cleared during the call
+            unsafe {
+                let c_func: *mut c_void = metacall_handle_function(handle.0, c_function.as_ptr());
+                if c_func.is_null() {
+                    return Err(Error::new(
+                        ErrorKind::FunctionNotFound,
+                        format!("Function '{}' not found in handle", func),
+                    ));
+                }
+                call_function(c_func, func, args)
+            }
+        })
+    })
+}
+
+/// Load the source into its own scope, call the function and unload it (i.e for evaluating
+/// snippets in a REPL), as it is unloaded after the call, the same source can be evaluated
+/// many times even if it defines functions that already exist, but its state is not kept
+pub fn eval(
+    tag: &str,
+    source: &str,
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<Any, Error> {
+    entry(|| {
+        let mut handle: *mut c_void = std::ptr::null_mut();
+        trace("Load from memory", tag, || {
+            load_from_cstr(tag, &to_cstring(source, "script")?, &mut handle)
+        })?;
+        let handle = Handle(handle);
+        // The handle must be cleared even if the call fails, so errors are returned after it
+        let ret = match handle_functions(&handle) {
+            Ok(functions) if !functions.iter().any(|f| f == func) => Err(Error::new(
+                ErrorKind::FunctionNotFound,
+                format!("Function '{}' is not defined by the evaluated source", func),
+            )),
+            _ => metacall_handle(&handle, func, args),
+        };
+        clear(handle)?;
+        ret
+    })
+}
+
+/// Call a function by name within the scope of an already loaded module, avoiding
+/// ambiguity when multiple scripts define a function with the same name; the module
+/// is named after the file name of the first script it was loaded from (i.e "script.py")
+pub fn metacall_in(
+    tag: &str,
+    module: &str,
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<Any, Error> {
+    entry(|| {
+        let c_tag = to_cstring(tag, "tag")?;
+        let c_module = to_cstring(module, "module name")?;
+        // SAFETY: c_tag and c_module live until the end of the function
+        let handle = unsafe { metacall_handle_by_name(c_tag.as_ptr(), c_module.as_ptr()) };
+        if handle.is_null() {
+            return Err(Error::new(
+                ErrorKind::FunctionNotFound,
+                format!("Module '{}' not found in loader '{}'", module, tag),
+            ));
+        }
+        metacall_handle(&Handle(handle), func, args)
+    })
+}
+
+/// Call a resolved MetaCall function converting the arguments and the return value,
+/// the name of the function is only used for describing the errors
+unsafe fn call_function(
+    c_func: *mut c_void,
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<Any, Error> {
+    call_function_with(c_func, func, args, &mut Vec::new(), &mut Vec::new())
+}
+
+/// Same as call_function but storing the converted arguments in c_args, which is left
+/// empty after the call, so its allocation can be reused between calls, like the buffer
+/// used for converting the strings (see any_to_metacall_in)
+///
+/// The caller owns the arguments and the return value, metacallfv_s does not take ownership
+/// of them, but when an argument has a different type than the declared by the function,
+/// it is casted and replaced in the array (destroying the original), so the values to be
+/// destroyed must be read from c_args after the call instead of being kept aside
+unsafe fn call_function_with(
+    c_func: *mut c_void,
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+    c_args: &mut Vec<*mut c_void>,
+    strings: &mut Vec<u8>,
+) -> Result<Any, Error> {
+    for arg in args {
+        match any_to_metacall_in(arg.borrow(), strings) {
+            Ok(value) => c_args.push(value),
+            Err(e) => {
+                destroy_values(c_args.drain(..));
+                return Err(e);
+            }
+        }
+    }
+    #[cfg(feature = "log")]
+    log::trace!(
+        "Calling function {:p} with {} arguments",
+        c_func,
+        c_args.len()
+    );
+    let ret: *mut c_void = with_args_array(c_args, |args, size| metacallfv_s(c_func, args, size));
+    destroy_values(c_args.drain(..));
+    // Functions returning null (i.e None in Python) return a null value, so no value
+    // at all means that the call failed (see the documentation of metacall)
+    if ret.is_null() {
+        return Err(call_failed_error(func));
+    }
+    let rt = metacall_to_any(ret);
+    metacall_value_destroy(ret);
+    Ok(rt)
+}
+
+/// Call the same function once per set of arguments, collecting the results in order;
+/// the function is resolved only once and the storage of the arguments (including the buffer
+/// used for converting strings) is reused between calls, so it is faster than calling metacall
+/// in a loop (i.e for processing a dataset)
+pub fn metacall_map<I>(func: &str, args: I) -> Result<Vec<Any>, Error>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[Any]>,
+{
+    entry(|| {
+        let function = function(func)?;
+        let mut c_args = Vec::new();
+        let mut strings = Vec::new();
+        args.into_iter()
+            .map(|args| unsafe {
+                call_function_with(function.0, func, args.as_ref(), &mut c_args, &mut strings)
+            })
+            .collect()
+    })
+}
+
+/// Call a function by name without arguments
+pub fn metacall0(func: &str) -> Result<Any, Error> {
+    entry(|| metacall(func, &[]))
+}
+
+/// Number of workers of metacall_with_timeout which have not finished yet, it is only
+/// incremented while MetaCall is initialized (holding the lock of INITIALIZED)
+static WORKERS: Mutex<usize> = Mutex::new(0);
+
+/// Notified each time a worker finishes, destroy waits on it until there are no workers
+static WORKERS_DONE: Condvar = Condvar::new();
+
+fn workers() -> MutexGuard<'static, usize> {
+    WORKERS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Registration of a running worker, dropped (even if the call panics) when it finishes
+struct Worker;
+
+impl Worker {
+    fn start() -> Result<Worker, Error> {
+        let count = initialized();
+        if *count == 0 {
+            return Err(Error::new(
+                ErrorKind::CallFailed,
+                "MetaCall is not initialized",
+            ));
+        }
+        *workers() += 1;
+        Ok(Worker)
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        *workers() -= 1;
+        WORKERS_DONE.notify_all();
+    }
+}
+
+/// Same as metacall but failing if the function does not return before the timeout
+///
+/// The call runs in a worker thread, this is supported by the loaders that synchronize
+/// calls from other threads (i.e Python takes the GIL and NodeJS forwards the call to its
+/// event loop thread). A running function cannot be interrupted, so after a timeout the
+/// script keeps running in the background and its result is discarded; destroying
+/// MetaCall (i.e dropping the guard) waits until all the workers have finished
+pub fn metacall_with_timeout(
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+    timeout: Duration,
+) -> Result<Any, Error> {
+    entry(|| {
+        let name = func.to_string();
+        let args: Vec<Any> = args.into_iter().map(|arg| arg.borrow().clone()).collect();
+        let (sender, receiver) = mpsc::channel();
+        let worker = Worker::start()?;
+        thread::spawn(move || {
+            // The receiver is gone if the call timed out, so the result can be discarded
+            let _ = sender.send(metacall(&name, &args));
+            // Everything referencing MetaCall must be released before the worker finishes
+            drop(args);
+            drop(worker);
+        });
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(Error::new(
+                ErrorKind::CallFailed,
+                format!("Function '{}' timed out after {:?}", func, timeout),
+            )),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::new(
+                ErrorKind::CallFailed,
+                format!("Function '{}' panicked in the worker thread", func),
+            )),
+        }
+    })
+}
+
+/// Same as metacall but taking the arguments by value, so they can be built inline
+/// (i.e `metacall_owned("f", vec![1.into(), "x".into()])`)
+pub fn metacall_owned(func: &str, args: impl IntoIterator<Item = Any>) -> Result<Any, Error> {
+    entry(|| {
+        let args: Vec<Any> = args.into_iter().collect();
+        metacall(func, &args)
+    })
+}
+
+/// Types that can be passed as the positional arguments of a call (see metacall_struct)
+///
+/// It is implemented for tuples of up to 6 elements (of types convertible into Any), and
+/// structs declared with args_struct!, which pass their fields in declaration order
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Any>;
+}
+
+impl IntoArgs for Vec<Any> {
+    fn into_args(self) -> Vec<Any> {
+        self
+    }
+}
+
+impl IntoArgs for () {
+    fn into_args(self) -> Vec<Any> {
+        Vec::new()
+    }
+}
+
+macro_rules! impl_into_args_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: Into<Any>),+> IntoArgs for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Any> {
+                let ($($name,)+) = self;
+                vec![$($name.into()),+]
+            }
+        }
+    };
+}
+
+impl_into_args_tuple!(A);
+impl_into_args_tuple!(A, B);
+impl_into_args_tuple!(A, B, C);
+impl_into_args_tuple!(A, B, C, D);
+impl_into_args_tuple!(A, B, C, D, E);
+impl_into_args_tuple!(A, B, C, D, E, F);
+
+/// Declare a struct whose fields are the positional arguments of a function, in declaration
+/// order, so it can be passed to metacall_struct (each field must be convertible into Any):
+///
+/// ```ignore
+/// metacall::args_struct! {
+///     struct Add { a: i64, b: i64 }
+/// }
+///
+/// let sum = metacall::metacall_struct("add", Add { a: 1, b: 2 })?;
+/// ```
+#[macro_export]
+macro_rules! args_struct {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident : $type:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $type),*
+        }
+
+        impl $crate::IntoArgs for $name {
+            fn into_args(self) -> Vec<$crate::Any> {
+                vec![$($crate::Any::from(self.$field)),*]
+            }
+        }
+    };
+}
+
+/// Call a function by name passing the arguments as a tuple or a struct (see IntoArgs)
+pub fn metacall_struct(func: &str, args: impl IntoArgs) -> Result<Any, Error> {
+    entry(|| metacall(func, args.into_args()))
+}
+
+/// Call a function by name converting each argument into Any through From,
+/// so arguments of different types can be mixed (i.e `metacall!("f", 1, 2.5, "a")`)
+///
+/// Literals get the default type of Rust, so `1` is an Any::Int and `2.5` an Any::Double,
+/// use suffixes to choose another width (i.e `2.5f32` for an Any::Float or `1i64` for an
+/// Any::Long), loaders with typed parameters cast the arguments into the declared types
+#[macro_export]
+macro_rules! metacall {
+    ($func:expr $(, $arg:expr)* $(,)?) => {
+        $crate::metacall($func, &[$($crate::Any::from($arg)),*])
+    };
+}
+
+/// Call a function by name and convert its result into T, call failures are returned
+/// as they are while a result of a different type produces a return type mismatch error
+///
+/// ```ignore
+/// let n: i64 = metacall::metacall_typed("pyfn", &[])?;
+/// ```
+pub fn metacall_typed<T>(
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<T, Error>
+where
+    T: TryFrom<Any>,
+    T::Error: fmt::Display,
+{
+    entry(|| {
+        let ret = metacall(func, args)?;
+        T::try_from(ret).map_err(|e| {
+            Error::new(
+                ErrorKind::ConversionFailed,
+                format!("Return type mismatch in function '{}': {}", func, e),
+            )
+        })
+    })
+}
+
+/// Rust function registered with register, it is boxed twice so it can be passed as a thin pointer
+type RegisteredFunction = Arc<dyn Fn(Vec<Any>) -> Result<Any, Error> + Send + Sync>;
+
+/// Functions registered with register by id, along with the name they were registered with;
+/// the id is the closure data of the MetaCall function, so the trampoline finds the function
+/// without storing Rust pointers in C
+static REGISTRY: Mutex<BTreeMap<usize, (String, RegisteredFunction)>> = Mutex::new(BTreeMap::new());
+
+/// Ids start at one, so the closure data of a registered function is never null, and they
+/// are never reused, so a removed function is not confused with a newer one
+static REGISTRY_NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Insert the function replacing the previous one registered with the same name, which is
+/// returned so it is dropped by the caller without holding the lock (it may own values that
+/// need other locks when dropped, i.e a FunctionValue); scripts still holding the replaced
+/// function get no value when calling it, like functions of a destroyed MetaCall instance
+fn registry_insert(name: &str, f: RegisteredFunction) -> (usize, Option<RegisteredFunction>) {
+    let id = REGISTRY_NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    let replaced = registry
+        .iter()
+        .find(|(_, (registered, _))| registered == name)
+        .map(|(id, _)| *id)
+        .and_then(|id| registry.remove(&id))
+        .map(|(_, f)| f);
+    registry.insert(id, (name.to_string(), f));
+    (id, replaced)
+}
+
+/// Remove all the registered functions, they belong to the MetaCall instance being destroyed
+fn registry_clear() -> BTreeMap<usize, (String, RegisteredFunction)> {
+    std::mem::take(&mut *REGISTRY.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// The function is cloned out of the registry, so the lock is not held while it runs,
+/// otherwise a function calling a script that calls another registered function deadlocks
+fn registry_get(id: usize) -> Option<RegisteredFunction> {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&id)
+        .map(|(_, f)| f.clone())
+}
+
+/// Run a Rust function called by a script, a panic cannot unwind into MetaCall (it would
+/// abort the process), so it is caught and reported as an error of the function
+fn callback_catch(f: impl FnOnce() -> Result<Any, Error>) -> Result<Any, Error> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+        .unwrap_or_else(|_| Err(Error::new(ErrorKind::CallFailed, "Rust function panicked")))
+}
+
+/// Convert the result of a Rust function called by a script into its return value, failures
+/// (an error returned by the function or a value that cannot be converted, i.e a string with a
+/// null byte) return no value, like the loaders do when a function throws; there is no way to
+/// report them to the script, so they are logged as warnings when the log feature is enabled
+unsafe fn callback_return(ret: Result<Any, Error>) -> *mut c_void {
+    let ret = ret.and_then(|ret| {
+        any_to_metacall(&ret).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("Invalid return value of a Rust function: {}", e),
+            )
+        })
+    });
+    match ret {
+        Ok(value) => value,
+        Err(_e) => {
+            #[cfg(feature = "log")]
+            log::warn!("{}", _e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Entry point for scripts calling a Rust function registered with register,
+/// the closure data of the MetaCall function is the id of the function in the registry
+extern "C" fn register_trampoline(
+    argc: usize,
+    argv: *mut *mut c_void,
+    data: *mut c_void,
+) -> *mut c_void {
+    unsafe {
+        let f = match registry_get(data as usize) {
+            Some(f) => f,
+            None => return std::ptr::null_mut(),
+        };
+        // Arguments are owned by the caller, the returned value is owned by MetaCall
+        let args = (0..argc)
+            .map(|i| {
+                let arg = *argv.add(i);
+                if arg.is_null() {
+                    Any::Null
+                } else {
+                    metacall_to_any(arg)
+                }
+            })
+            .collect();
+        callback_return(callback_catch(|| f(args)))
+    }
+}
+
+/// Register a Rust function so it can be called by name from the loaded scripts, the
+/// types describe the parameters and the return value (use MetacallValueId::Invalid
+/// for dynamically typed ones); the function lives until it is registered again with the
+/// same name (which replaces it) or MetaCall is destroyed
+///
+/// If the returned value cannot be converted into a MetaCall value (i.e a string with a null
+/// byte), the call returns no value, see register_fallible
+pub fn register(
+    name: &str,
+    f: impl Fn(Vec<Any>) -> Any + Send + Sync + 'static,
+    arg_types: &[MetacallValueId],
+    ret_type: MetacallValueId,
+) -> Result<(), Error> {
+    entry(|| register_fallible(name, move |args| Ok(f(args)), arg_types, ret_type))
+}
+
+/// Same as register but for functions that can fail; this version of MetaCall cannot throw
+/// exceptions into the scripts (it has no throwable value type), so on errors the call
+/// returns no value, which is how the loaders report their exceptions (i.e Rust calls to the
+/// function fail, while Python receives None), and the error is logged as a warning when
+/// the log feature is enabled; the same happens if the returned value cannot be converted
+pub fn register_fallible(
+    name: &str,
+    f: impl Fn(Vec<Any>) -> Result<Any, Error> + Send + Sync + 'static,
+    arg_types: &[MetacallValueId],
+    ret_type: MetacallValueId,
+) -> Result<(), Error> {
+    entry(|| {
+        let function_name = name.to_string();
+        let f = move |args| {
+            f(args).map_err(|e| {
+                Error::new(
+                    ErrorKind::CallFailed,
+                    format!("Rust function '{}' failed: {}", function_name, e),
+                )
+            })
+        };
+        let c_name = to_cstring(name, "function name")?;
+        let mut func: *mut c_void = std::ptr::null_mut();
+        // SAFETY: c_name and arg_types live until the end of the function, MetaCall copies both
+        unsafe {
+            if metacall_registerv(
+                c_name.as_ptr(),
+                register_trampoline,
+                &mut func,
+                ret_type,
+                arg_types.len(),
+                arg_types.as_ptr(),
+            ) != 0
+            {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("Failed to register function '{}'", name),
+                ));
+            }
+            // The closure can only be bound through a function value, the registered
+            // function keeps its own reference, so the temporary value can be destroyed
+            let (id, replaced) = registry_insert(name, Arc::new(f));
+            metacall_value_destroy(metacall_value_create_function_closure(
+                func,
+                id as *mut c_void,
+            ));
+            drop(replaced);
+        }
+        Ok(())
+    })
+}
+
+/// Shared state between the awaiting Rust future and the MetaCall callbacks
+struct AwaitState {
+    result: Option<Result<Any, Error>>,
+    waker: Option<Waker>,
+}
+
+/// Result of an asynchronous call, completed by its resolve or reject callback, it can be
+/// awaited (it is a Future) or polled without blocking with try_resolve (i.e from a custom
+/// event loop without an async runtime); it is only created for async functions (see
+/// metacall_future), whose futures are settled, so try_resolve does not stay None forever
+pub struct PendingFuture(Arc<Mutex<AwaitState>>);
+
+impl PendingFuture {
+    /// Take the result if the future has already settled, None if it is still pending;
+    /// the result is only returned once, after that it is None again
+    pub fn try_resolve(&self) -> Option<Result<Any, Error>> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .result
+            .take()
+            .map(|result| result.map_err(fail))
+    }
+}
+
+impl Future for PendingFuture {
+    type Output = Result<Any, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.result.take() {
+            Some(result) => Poll::Ready(result.map_err(fail)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Store the result of the future and wake up the task awaiting it, only one of the
+/// callbacks is ever called, so it takes back the reference leaked by metacall_await
+unsafe fn await_settle(data: *mut c_void, result: Result<Any, Error>) {
+    let state = Arc::from_raw(data as *const Mutex<AwaitState>);
+    let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+    state.result = Some(result);
+    if let Some(waker) = state.waker.take() {
+        waker.wake();
+    }
+}
+
+/// Convert the value a future settled with, rejections are reported as an error
+unsafe fn await_result(value: *mut c_void, resolved: bool) -> Result<Any, Error> {
+    let result = if value.is_null() {
+        Any::Null
+    } else {
+        metacall_to_any(value)
+    };
+    if resolved {
+        Ok(result)
+    } else {
+        Err(Error::new(
+            ErrorKind::CallFailed,
+            format!("Future rejected with: {:?}", result),
+        ))
+    }
+}
+
+extern "C" fn await_resolve(value: *mut c_void, data: *mut c_void) -> *mut c_void {
+    // A panic (i.e from the waker) must not unwind into MetaCall
+    let _ = std::panic::catch_unwind(|| unsafe { await_settle(data, await_result(value, true)) });
+    std::ptr::null_mut()
+}
+
+extern "C" fn await_reject(value: *mut c_void, data: *mut c_void) -> *mut c_void {
+    let _ = std::panic::catch_unwind(|| unsafe { await_settle(data, await_result(value, false)) });
+    std::ptr::null_mut()
+}
+
+type AwaitCallback = extern "C" fn(*mut c_void, *mut c_void) -> *mut c_void;
+
+/// Start an asynchronous call whose result is passed with data to resolve or reject,
+/// if it fails none of them is ever called, so data must be released by the caller
+unsafe fn await_call(
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+    resolve: AwaitCallback,
+    reject: AwaitCallback,
+    data: *mut c_void,
+) -> Result<(), Error> {
+    let c_function = to_cstring(func, "function name")?;
+    // SAFETY: c_function lives until the end of the function
+    let c_func: *mut c_void = metacall_function(c_function.as_ptr());
+    if c_func.is_null() {
+        return Err(Error::new(
+            ErrorKind::FunctionNotFound,
+            format!("Function '{}' not found", func),
+        ));
+    }
+    // Loaders return a future for synchronous functions too (i.e NodeJS), but it is never
+    // settled, so the callbacks would never be called and the call would wait forever
+    if metacall_function_async(c_func) != 1 {
+        return Err(Error::new(
+            ErrorKind::CallFailed,
+            format!(
+                "Function '{}' is not asynchronous, it cannot be awaited",
+                func
+            ),
+        ));
+    }
+    let mut c_args = any_to_metacall_list(args)?;
+    let ret = with_args_array(&mut c_args, |args, size| {
+        metacallfv_await_s(c_func, args, size, resolve, reject, data)
+    });
+    destroy_values(c_args);
+    if ret.is_null() {
+        return Err(Error::new(
+            ErrorKind::CallFailed,
+            format!("Function '{}' could not be awaited", func),
+        ));
+    }
+    // Anything other than a future means the loader did not await the call,
+    // so the callbacks will never be called
+    let id = MetacallValueId::from_raw(metacall_value_id(ret));
+    metacall_value_destroy(ret);
+    if id != Some(MetacallValueId::Future) {
+        return Err(Error::new(
+            ErrorKind::CallFailed,
+            format!("Function '{}' did not return a future", func),
+        ));
+    }
+    Ok(())
+}
+
+/// Call an asynchronous function (a NodeJS or TypeScript async function) and return its
+/// future without waiting for it, it fails if the function is not declared as async; the
+/// rest of loaders (i.e Python) do not implement await yet, so their functions always fail
+///
+/// Futures can only be created by the loaders, MetaCall does not provide a way to create
+/// a future from the host and resolve it later (metacall_value_create_future wraps a
+/// future implemented by a loader), so Rust cannot pass pending values to the scripts
+pub fn metacall_future(
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<PendingFuture, Error> {
+    entry(|| {
+        let state = Arc::new(Mutex::new(AwaitState {
+            result: None,
+            waker: None,
+        }));
+        // The reference is released by the callback, or here if the call could not be awaited
+        // (i.e the function is not async); it is only leaked if MetaCall is destroyed before
+        // the future settles
+        let data = Arc::into_raw(state.clone()) as *mut c_void;
+        if let Err(e) = unsafe { await_call(func, args, await_resolve, await_reject, data) } {
+            drop(unsafe { Arc::from_raw(data as *const Mutex<AwaitState>) });
+            return Err(e);
+        }
+        Ok(PendingFuture(state))
+    })
+}
+
+/// Call an asynchronous function (a NodeJS or TypeScript async function) and await until its
+/// future is resolved or rejected, it fails like metacall_future with non async functions
+pub async fn metacall_await(
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<Any, Error> {
+    metacall_future(func, args)?.await
+}
+
+/// Release one initialization, MetaCall is only torn down when the last one is
+/// released, calling it when MetaCall is not initialized does nothing
+pub fn destroy() {
+    let mut count = initialized();
+    // The workers of metacall_with_timeout may be still calling into MetaCall, they are waited
+    // without holding the lock (they may need it, i.e for dropping a FunctionValue), and new
+    // workers cannot start while it is held, so the count is checked again after waiting
+    while *count == 1 && *workers() > 0 {
+        drop(count);
+        let running = workers();
+        drop(
+            WORKERS_DONE
+                .wait_while(running, |running| *running > 0)
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        count = initialized();
+    }
+    let mut registered = BTreeMap::new();
+    if *count == 1 {
+        unsafe {
+            metacall_destroy();
+        }
+        GENERATION.fetch_add(1, Ordering::SeqCst);
+        registered = registry_clear();
+    }
+    *count = count.saturating_sub(1);
+    // The registered functions are dropped after releasing the lock, see registry_insert
+    drop(count);
+    drop(registered);
+}
+
+/// Serialize a value into JSON using the MetaCall serial (the same
+/// representation MetaCall uses for introspection and communication)
+pub fn serialize(value: &Any) -> Result<String, Error> {
+    entry(|| {
+        let allocator = Allocator::new()?;
+        unsafe {
+            let v = any_to_metacall(value)?;
+            let mut size: usize = 0;
+            let st = metacall_serialize(metacall_serial(), v, &mut size, allocator.0);
+            metacall_value_destroy(v);
+            if st.is_null() {
+                return Err(Error::new(
+                    ErrorKind::ConversionFailed,
+                    format!("Failed to serialize {:?}", value),
+                ));
+            }
+            let json = std::ffi::CStr::from_ptr(st).to_string_lossy().into_owned();
+            metacall_allocator_free(allocator.0, st as *mut c_void);
+            Ok(json)
+        }
+    })
+}
+
+/// Deserialize JSON into a value using the MetaCall serial
+pub fn deserialize(json: &str) -> Result<Any, Error> {
+    entry(|| {
+        let allocator = Allocator::new()?;
+        let c_json = to_cstring(json, "JSON")?;
+        // SAFETY: c_json and the allocator live until the end of the function
+        unsafe {
+            // The size must include the null terminator of the string
+            let v = metacall_deserialize(
+                metacall_serial(),
+                c_json.as_ptr(),
+                c_json.as_bytes_with_nul().len(),
+                allocator.0,
+            );
+            if v.is_null() {
+                return Err(Error::new(
+                    ErrorKind::ConversionFailed,
+                    format!("Failed to deserialize {:?}", json),
+                ));
+            }
+            let value = metacall_to_any(v);
+            metacall_value_destroy(v);
+            Ok(value)
+        }
+    })
+}
+
+/// Common items for typical usage, `use metacall::prelude::*;` imports all of them
+pub mod prelude {
+    // It re-exports both the metacall function and the metacall! macro
+    pub use crate::metacall;
+    // The guard destroys MetaCall, destroy is not included since calling it while a guard is
+    // alive would break the reference count of the initializations
+    pub use crate::{initialize, load_from_file, load_from_memory, Any, Error, MetacallGuard};
+}
+
+/// Doc test to check if the code can build an run
+///
+/// The tests which call into MetaCall need libmetacall and the mock loader, so they are only
+/// built with the ffi-tests feature (`cargo test --features ffi-tests`), the rest only check
+/// the conversions in Rust, so they run anywhere (`cargo test --lib`); the examples of the
+/// documentation are only compiled (no_run), since linking them requires libmetacall
+#[cfg(test)]
+mod tests {
+    struct Defer<F: FnOnce()>(Option<F>);
+
+    impl<F: FnOnce()> Drop for Defer<F> {
+        fn drop(&mut self) {
+            if let Some(f) = self.0.take() {
+                f()
+            }
+        }
+    }
+
+    /// Defer execution of a closure until the constructed value is dropped
+    /// Works at the end of the scope or manual drop() function
+    pub fn defer<F: FnOnce()>(f: F) -> impl Drop {
+        Defer(Some(f))
+    }
+
+    /// Tests that initialize MetaCall must not run concurrently,
+    /// otherwise the reference count of one test leaks into the other
+    #[cfg(feature = "ffi-tests")]
+    static METACALL_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    #[cfg(feature = "ffi-tests")]
+    fn metacall_lock() -> std::sync::MutexGuard<'static, ()> {
+        METACALL_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    #[test]
+    fn test_defer() {
+        use std::cell::RefCell;
+
+        let i = RefCell::new(0);
+
+        {
+            let _d = defer(|| *i.borrow_mut() += 1);
+            assert_eq!(*i.borrow(), 0);
+        }
+
+        assert_eq!(*i.borrow(), 1);
+    }
+
+    #[test]
+    fn test_from_str() {
+        use crate::Any;
+
+        let args: [Any; 2] = ["a".into(), String::from("b").into()];
+
+        assert!(matches!(&args[0], Any::Str(s) if s == "a"));
+        assert!(matches!(&args[1], Any::Str(s) if s == "b"));
+    }
+
+    #[test]
+    fn test_from_unsigned() {
+        use crate::Any;
+        use std::convert::TryFrom;
+
+        assert!(matches!(Any::from(u16::MAX), Any::Int(65535)));
+        assert!(matches!(Any::from(u32::MAX), Any::Long(4294967295)));
+        assert!(matches!(
+            Any::try_from(i64::MAX as u64),
+            Ok(Any::Long(i64::MAX))
+        ));
+        assert!(Any::try_from(u64::MAX).is_err());
+        assert!(matches!(Any::try_from(7usize), Ok(Any::Long(7))));
+    }
+
+    #[test]
+    fn test_from_vec() {
+        use crate::Any;
+        use std::convert::TryFrom;
+
+        let array: Any = vec![1, 2, 3].into();
+        assert_eq!(
+            array,
+            Any::Array(vec![Any::Int(1), Any::Int(2), Any::Int(3)])
+        );
+
+        assert_eq!(Any::from(vec![1u8, 2]), Any::Buffer(vec![1, 2]));
+        assert_eq!(
+            Vec::<u8>::try_from(Any::from(vec![1u8, 2])).unwrap(),
+            vec![1, 2]
+        );
+        assert_eq!(Any::from(&b"ab"[..]), Any::Buffer(b"ab".to_vec()));
+
+        let nested = Any::from([vec!["a"], vec![]]);
+        assert_eq!(
+            nested,
+            Any::Array(vec![
+                Any::Array(vec![Any::Str("a".to_string())]),
+                Any::Array(vec![])
+            ])
+        );
+    }
+
+    #[test]
+    fn test_from_char_and_unit() {
+        use crate::Any;
+        use std::os::raw::c_char;
+
+        assert_eq!(Any::from('a'), Any::Char('a'));
+        assert_eq!(Any::from('é'), Any::Char('é'));
+        assert_eq!(Any::from(()), Any::Null);
+        assert_eq!(Any::default(), Any::Null);
+        assert_eq!(Any::from(b'a' as c_char), Any::Char('a'));
+        assert_eq!(Any::from(0xe9u8 as c_char), Any::Buffer(vec![0xe9]));
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_loader() {
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        let handles = crate::Loader::new()
+            .file("mock", "loader_a.mock")
+            .file("mock", "loader_b.mock")
+            .load_handles()
+            .unwrap();
+        assert_eq!(handles.len(), 2);
+
+        let error = crate::Loader::new()
+            .file("mock", "loader_c.mock")
+            .file("none", "loader_d.none")
+            .load()
+            .unwrap_err();
+        assert!(error.to_string().contains("loader_d.none"));
+        assert!(error
+            .to_string()
+            .contains("the loader 'none' is not available"));
+        assert_eq!(error.kind(), crate::ErrorKind::LoaderNotFound);
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_raw_value_copy() {
+        use crate::raw::Value;
+        use crate::Any;
+
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        let any = Any::Array(vec![Any::Int(1), Any::Str("a".to_string())]);
+        let value = Value::from_any(&any).unwrap();
+        let copy = value.copy().unwrap();
+        drop(value);
+        assert_eq!(copy.to_any(), any);
+        assert_eq!(copy.type_name(), "array");
+
+        let value = Value::int(3);
+        assert_eq!(value.to_int(), Some(3));
+        assert_eq!(value.to_long(), None);
+        assert_eq!(Value::buffer(&[1, 2]).as_bytes(), Some(&[1u8, 2][..]));
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_call_without_return_value() {
+        use crate::{Any, MetacallValueId};
+        use std::os::raw::c_void;
+
+        extern "C" fn fail(_: usize, _: *mut *mut c_void, _: *mut c_void) -> *mut c_void {
+            std::ptr::null_mut()
+        }
+
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        let mut func: *mut c_void = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                crate::metacall_registerv(
+                    std::ptr::null(),
+                    fail,
+                    &mut func,
+                    MetacallValueId::Invalid,
+                    0,
+                    std::ptr::null()
+                ),
+                0
+            );
+            assert!(crate::call_function(func, "fail", [] as [Any; 0]).is_err());
+            // The value owns the anonymous function, so destroying it releases the function
+            crate::metacall_value_destroy(crate::metacall_value_create_function_closure(
+                func,
+                std::ptr::null_mut(),
+            ));
+        }
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_function_value_release() {
+        use crate::Any;
+
+        fn callback(value: Any) -> Any {
+            value
+        }
+
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        // Each value registers an anonymous function which must be released with the value
+        // (run the tests with valgrind to check that nothing is leaked)
+        for _ in 0..1000 {
+            unsafe {
+                let c_value = crate::any_to_metacall(&Any::Function(Box::new(callback))).unwrap();
+                crate::metacall_value_destroy(c_value);
+            }
+        }
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_call_value() {
+        use crate::Any;
+
+        fn double(value: Any) -> Any {
+            Any::Long(value.as_i64().unwrap() * 2)
+        }
+
+        let _lock = metacall_lock();
+        let guard = crate::initialize().unwrap();
+
+        // Simulate a script returning a function, the returned Any must keep it alive
+        // after the original value is destroyed
+        let func = unsafe {
+            let c_value = crate::any_to_metacall(&Any::Function(Box::new(double))).unwrap();
+            let func = crate::metacall_to_any(c_value);
+            crate::metacall_value_destroy(c_value);
+            func
+        };
+        assert!(func.is_function());
+        assert_eq!(func.clone(), func);
+        assert_eq!(
+            crate::call_value(&func, [&Any::Long(21)]).unwrap(),
+            Any::Long(42)
+        );
+        assert!(crate::call_value(&Any::Int(1), &[]).is_err());
+        // Rust functions take a single argument, the rest are not silently dropped
+        assert!(crate::call_value(&func, [&Any::Long(1), &Any::Long(2)]).is_err());
+
+        // Once MetaCall is destroyed the value can neither be called nor passed to it,
+        // and dropping it does not touch MetaCall (even after initializing it again)
+        drop(guard);
+        assert!(crate::call_value(&func, [&Any::Long(21)]).is_err());
+        let _guard = crate::initialize().unwrap();
+        assert!(crate::call_value(&func, [&Any::Long(21)]).is_err());
+        assert!(unsafe { crate::any_to_metacall(&func) }.is_err());
+        drop(func);
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_utf8_string_round_trip() {
+        use crate::Any;
+
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        // MetaCall expects the length in bytes (without the null terminator)
+        let value = Any::Str("héllo 🦀".to_string());
+        unsafe {
+            let c_value = crate::any_to_metacall(&value).unwrap();
+            assert_eq!(crate::metacall_value_size(c_value), "héllo 🦀".len() + 1);
+            assert_eq!(crate::metacall_to_any(c_value), value);
+            crate::metacall_value_destroy(c_value);
+        }
+
+        // The function receives the whole string, not the first chars of it
+        crate::register(
+            "rust_str_len",
+            |args| Any::Long(args[0].as_str().map_or(-1, |x| x.len() as i64)),
+            &[crate::MetacallValueId::String],
+            crate::MetacallValueId::Long,
+        )
+        .unwrap();
+        assert_eq!(
+            crate::metacall("rust_str_len", [&value]).unwrap(),
+            Any::Long("héllo 🦀".len() as i64)
+        );
+        register_echo();
+        assert_eq!(crate::metacall("rust_echo", [&value]).unwrap(), value);
+
+        if crate::loader_available("py") {
+            let source = "def py_str(x):\n    return [len(x), x]\n";
+            assert_eq!(
+                crate::eval("py", source, "py_str", [&value]).unwrap(),
+                Any::Array(vec![Any::Long(7), value])
+            );
+        }
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_bool_round_trip() {
+        use crate::Any;
+
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        unsafe {
+            for value in [Any::Bool(true), Any::Bool(false)] {
+                let c_value = crate::any_to_metacall(&value).unwrap();
+                assert_eq!(crate::metacall_to_any(c_value), value);
+                crate::metacall_value_destroy(c_value);
+            }
+
+            // Booleans different from 0 and 1 are also true
+            let c_value = crate::metacall_value_create_bool(2);
+            assert_eq!(crate::metacall_to_any(c_value), Any::Bool(true));
+            crate::metacall_value_destroy(c_value);
+        }
+
+        // Functions called through MetaCall receive and return the same booleans
+        register_echo();
+        for value in [Any::Bool(true), Any::Bool(false)] {
+            assert_eq!(crate::metacall("rust_echo", [&value]).unwrap(), value);
+        }
+
+        // Python receives True and False (not integers) and its booleans come back as bools,
+        // the Python loader is optional for these tests, so it is only checked if installed
+        if crate::loader_available("py") {
+            let source = "def py_bools(x):\n    return [x is True, not x, x]\n";
+            assert_eq!(
+                crate::eval("py", source, "py_bools", &[Any::Bool(true)]).unwrap(),
+                Any::Array(vec![Any::Bool(true), Any::Bool(false), Any::Bool(true)])
+            );
+        }
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_option_argument() {
+        use crate::Any;
+
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        // None is passed as the null value and Some as the value it contains
+        register_echo();
+        assert_eq!(
+            crate::metacall("rust_echo", [Any::from(None::<i32>)]).unwrap(),
+            Any::Null
+        );
+        assert_eq!(
+            crate::metacall("rust_echo", [Any::from(Some(3))]).unwrap(),
+            Any::Int(3)
+        );
+        assert_eq!(
+            crate::metacall("new_args", [Any::from(Some("a"))]).unwrap(),
+            Any::Str("Hello World".to_string())
+        );
+
+        // Python receives None, the Python loader is optional for these tests
+        if crate::loader_available("py") {
+            let source = "def py_is_none(x):\n    return x is None\n";
+            for (value, is_none) in [(Any::from(None::<i32>), true), (Any::from(Some(1)), false)] {
+                assert_eq!(
+                    crate::eval("py", source, "py_is_none", [value]).unwrap(),
+                    Any::Bool(is_none)
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_initialize_with() {
+        let _lock = metacall_lock();
+
+        let options = crate::InitOptions::new().execution_path("mock", ".");
+        let _guard = crate::initialize_with(&options).unwrap();
+        assert!(crate::is_initialized());
+
+        let options = crate::InitOptions::new().execution_path("mock", "a\0b");
+        assert!(crate::initialize_with(&options).is_err());
+        assert!(crate::is_initialized());
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_register_fallible() {
+        use crate::{Any, Error, ErrorKind, MetacallValueId};
+
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        crate::register_fallible(
+            "rust_checked_div",
+            |args| match (args[0].as_i64(), args[1].as_i64()) {
+                (Some(_), Some(0)) => Err(Error::new(ErrorKind::CallFailed, "division by zero")),
+                (Some(a), Some(b)) => Ok(Any::Long(a / b)),
+                _ => Err(Error::new(ErrorKind::CallFailed, "expected two integers")),
+            },
+            &[MetacallValueId::Long, MetacallValueId::Long],
+            MetacallValueId::Long,
+        )
+        .unwrap();
+
+        assert_eq!(
+            crate::metacall("rust_checked_div", &[Any::Long(6), Any::Long(3)]).unwrap(),
+            Any::Long(2)
+        );
+        assert!(crate::metacall("rust_checked_div", &[Any::Long(6), Any::Long(0)]).is_err());
+
+        // A return value that cannot be converted is reported like an error of the function
+        crate::register(
+            "rust_null_byte",
+            |_| Any::Str("a\0b".to_string()),
+            &[],
+            MetacallValueId::String,
+        )
+        .unwrap();
+        assert!(crate::metacall("rust_null_byte", &[]).is_err());
+
+        // Panics do not unwind into MetaCall, the call just fails
+        crate::register(
+            "rust_panics",
+            |_| panic!("panic"),
+            &[],
+            MetacallValueId::Null,
+        )
+        .unwrap();
+        assert!(crate::metacall("rust_panics", &[]).is_err());
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_float_width() {
+        use crate::{Any, MetacallValueId};
+
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        // Scripts receive single precision values for f32 and double precision for f64
+        for (value, id) in [
+            (Any::from(1.5f32), MetacallValueId::Float),
+            (Any::from(1.5), MetacallValueId::Double),
+        ] {
+            unsafe {
+                let c_value = crate::any_to_metacall(&value).unwrap();
+                assert_eq!(crate::metacall_value_id(c_value), id as std::os::raw::c_int);
+                assert_eq!(crate::metacall_to_any(c_value), value);
+                crate::metacall_value_destroy(c_value);
+            }
+        }
+
+        // The width is kept when the value is passed to a function called through MetaCall
+        register_echo();
+        assert_eq!(
+            crate::metacall("rust_echo", &[Any::from(1.5f32)]).unwrap(),
+            Any::Float(1.5)
+        );
+        assert_eq!(
+            crate::metacall!("rust_echo", 1.5).unwrap(),
+            Any::Double(1.5)
+        );
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_char_round_trip() {
+        use crate::Any;
+
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        for (value, expected) in [
+            (Any::Char('a'), Any::Char('a')),
+            (Any::Char('é'), Any::Str("é".to_string())),
+            (Any::Char('🦀'), Any::Str("🦀".to_string())),
+        ] {
+            unsafe {
+                let c_value = crate::any_to_metacall(&value).unwrap();
+                assert_eq!(crate::metacall_to_any(c_value), expected);
+                crate::metacall_value_destroy(c_value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_option() {
+        use crate::Any;
+
+        assert_eq!(Any::from(None::<i32>), Any::Null);
+        assert_eq!(Any::from(Some(3)), Any::Int(3));
+        assert_eq!(Any::from(Some("a")), Any::Str("a".to_string()));
+    }
+
+    #[test]
+    fn test_hash_map() {
+        use crate::Any;
+        use std::collections::HashMap;
+        use std::convert::TryFrom;
+
+        let mut map: HashMap<String, i64> = HashMap::new();
+        map.insert("a".to_string(), 1);
+        map.insert("b".to_string(), 2);
+
+        let value = Any::from(map);
+        assert!(matches!(&value, Any::Map(entries) if entries.len() == 2));
+
+        let back = HashMap::<String, Any>::try_from(value).unwrap();
+        assert_eq!(back.get("a"), Some(&Any::Long(1)));
+        assert_eq!(back.get("b"), Some(&Any::Long(2)));
+
+        let invalid = Any::Map(vec![(Any::Int(1), Any::Null)]);
+        assert!(HashMap::<String, Any>::try_from(invalid).is_err());
+    }
+
+    #[test]
+    fn test_try_from_any() {
+        use crate::Any;
+        use std::convert::TryInto;
+
+        let s: String = Any::Str("a".to_string()).try_into().unwrap();
+        assert_eq!(s, "a");
+
+        let n: i64 = Any::Int(23).try_into().unwrap();
+        assert_eq!(n, 23);
+
+        let n: Result<i32, _> = Any::Long(i64::MAX).try_into();
+        assert!(n.is_err());
+
+        let d: f64 = Any::Float(1.5).try_into().unwrap();
+        assert_eq!(d, 1.5);
+
+        let f: Result<f32, _> = Any::Double(1.5).try_into();
+        assert!(f.is_err());
+
+        let b: Result<bool, _> = Any::Null.try_into();
+        assert!(b.is_err());
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_null_byte_errors() {
+        use crate::Any;
+
+        assert!(crate::load_from_memory("py\0", "").is_err());
+        assert!(crate::load_from_bytes("py", b"a\0b").is_err());
+        assert!(crate::load_from_bytes("py", b"a\0b\0").is_err());
+        assert!(crate::metacall("f\0", &[]).is_err());
+
+        let arg = Any::Array(vec![Any::Int(1), Any::Str("a\0b".to_string())]);
+        assert!(unsafe { crate::any_to_metacall(&arg) }.is_err());
+
+        // Invalid values must not panic halfway through the argument list
+        let args = [Any::Int(1), Any::Null, Any::Str("a\0".to_string())];
+        assert!(unsafe { crate::any_to_metacall_list(&args) }.is_err());
+    }
+
+    #[test]
+    fn test_overflow() {
+        use crate::{Any, Overflow};
+
+        assert_eq!(Any::int_from(7, Overflow::Error).unwrap(), Any::Int(7));
+        assert!(Any::int_from(1 << 40, Overflow::Error).is_err());
+        assert_eq!(
+            Any::short_from(-100000, Overflow::Saturate).unwrap(),
+            Any::Short(i16::MIN)
+        );
+        assert_eq!(
+            Any::long_from(u64::MAX as i128, Overflow::Saturate).unwrap(),
+            Any::Long(i64::MAX)
+        );
+        assert_eq!(
+            Any::short_from(65537, Overflow::Wrap).unwrap(),
+            Any::Short(1)
+        );
+    }
+
+    #[test]
+    fn test_128_bit_integers() {
+        use crate::Any;
+        use std::convert::TryFrom;
+
+        assert_eq!(Any::try_from(-5i128).unwrap(), Any::Long(-5));
+        assert_eq!(
+            Any::try_from(i64::MAX as u128).unwrap(),
+            Any::Long(i64::MAX)
+        );
+        assert!(Any::try_from(i64::MIN as i128 - 1).is_err());
+        assert!(Any::try_from(u128::MAX).is_err());
+
+        assert_eq!(i128::try_from(Any::Int(-3)).unwrap(), -3);
+        assert_eq!(u128::try_from(Any::Long(3)).unwrap(), 3);
+        assert!(u128::try_from(Any::Long(-3)).is_err());
+    }
+
+    #[test]
+    fn test_into_args() {
+        use crate::{Any, IntoArgs};
+
+        crate::args_struct! {
+            #[derive(Clone)]
+            struct Add {
+                a: i64,
+                b: &'static str,
+            }
+        }
+
+        let add = Add { a: 1, b: "two" };
+        assert_eq!(
+            add.clone().into_args(),
+            [Any::Long(1), Any::Str("two".to_string())]
+        );
+        assert_eq!((1, 2.5).into_args(), [Any::Int(1), Any::Double(2.5)]);
+        assert_eq!(().into_args(), []);
+    }
+
+    #[test]
+    fn test_try_from_tuple() {
+        use crate::Any;
+        use std::convert::TryInto;
+
+        let value = Any::Array(vec![Any::Long(1), Any::Str("a".to_string())]);
+        let (a, b): (i64, String) = value.try_into().unwrap();
+        assert_eq!((a, b), (1, "a".to_string()));
+
+        let value = Any::Array(vec![Any::Long(1)]);
+        let error = TryInto::<(i64, i64)>::try_into(value).unwrap_err();
+        assert!(error.to_string().contains("1 elements into a tuple of 2"));
+
+        let value = Any::Array(vec![Any::Long(1), Any::Long(2)]);
+        assert!(TryInto::<(i64, String)>::try_into(value).is_err());
+    }
+
+    #[test]
+    fn test_accessors() {
+        use crate::Any;
+
+        let value = Any::Array(vec![Any::Str("a".to_string()), Any::Bool(true)]);
+        let array = value.as_array().unwrap();
+        assert_eq!(array[0].as_str(), Some("a"));
+        assert_eq!(array[1].as_bool(), Some(true));
+        assert_eq!(array[1].as_str(), None);
+        assert_eq!(value.as_map(), None);
+        assert_eq!(Any::Buffer(vec![1]).as_buffer(), Some(&[1u8][..]));
+    }
+
+    #[test]
+    fn test_get_and_len() {
+        use crate::Any;
+
+        let array = Any::Array(vec![Any::Int(1), Any::Int(2)]);
+        assert_eq!(array.get(0), Some(&Any::Int(1)));
+        assert_eq!(array.get(2), None);
+        assert_eq!(array.len(), Some(2));
+
+        let map = Any::Map(vec![(Any::Str("a".to_string()), Any::Int(1))]);
+        assert_eq!(map.get(0), None);
+        assert_eq!(map.len(), Some(1));
+        assert_eq!(map.is_empty(), Some(false));
+        assert_eq!(Any::Array(vec![]).is_empty(), Some(true));
+
+        assert_eq!(Any::Int(1).get(0), None);
+        assert_eq!(Any::Str("ab".to_string()).len(), None);
+    }
+
+    #[test]
+    fn test_try_into_vec() {
+        use crate::Any;
+
+        let array = Any::Array(vec![Any::Long(1), Any::Int(2), Any::Short(3)]);
+        assert_eq!(array.try_into_vec::<i64>().unwrap(), [1, 2, 3]);
+
+        let array = Any::Array(vec![Any::Long(1), Any::Str("2".to_string())]);
+        assert_eq!(
+            array.try_into_vec::<i64>().unwrap_err().to_string(),
+            "Invalid element 1 of the array: Cannot convert string Str(\"2\") into i64"
+        );
+        assert!(Any::Long(1).try_into_vec::<i64>().is_err());
+        assert!(Any::Array(vec![])
+            .try_into_vec::<String>()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_last_error() {
+        use crate::{entry, Error, ErrorKind};
+
+        let fails =
+            |message: &'static str| entry(|| Err::<(), _>(Error::new(ErrorKind::Other, message)));
+
+        // Errors that are created but not returned by an operation are not recorded
+        let _ = Error::new(ErrorKind::Other, "unused");
+        assert_eq!(crate::last_error(), None);
+        // Neither are the ones handled by an operation built on another
+        assert!(entry(|| {
+            assert!(fails("handled").is_err());
+            Ok(())
+        })
+        .is_ok());
+        assert_eq!(crate::last_error(), None);
+
+        assert!(fails("first").is_err());
+        assert_eq!(crate::last_error().as_deref(), Some("first"));
+        assert!(entry(|| Ok(())).is_ok());
+        assert_eq!(crate::last_error().as_deref(), Some("first"));
+        assert!(entry(|| fails("nested")).is_err());
+        assert_eq!(crate::last_error().as_deref(), Some("nested"));
+
+        let other = std::thread::spawn(crate::last_error).join().unwrap();
+        assert_eq!(other, None);
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_last_error_after_calls() {
+        use crate::{Any, MetacallValueId};
+
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        // The lookup of the missing function fails inside a call that succeeds
+        crate::register(
+            "rust_ignores_error",
+            |_| Any::Bool(crate::metacall("does_not_exist_inside", &[]).is_err()),
+            &[],
+            MetacallValueId::Bool,
+        )
+        .unwrap();
+
+        let error = crate::metacall("does_not_exist", &[]).unwrap_err();
+        assert_eq!(crate::last_error(), Some(error.to_string()));
+        assert_eq!(
+            crate::metacall("rust_ignores_error", &[]).unwrap(),
+            Any::Bool(true)
+        );
+        assert_eq!(crate::last_error(), Some(error.to_string()));
+    }
+
+    #[test]
+    fn test_merge() {
+        use crate::Any;
+
+        let key = |k: &str| Any::Str(k.to_string());
+        let defaults = Any::Map(vec![(key("a"), Any::Long(1)), (key("b"), Any::Long(2))]);
+        let options = Any::Map(vec![(key("c"), Any::Long(3)), (key("a"), Any::Null)]);
+        assert_eq!(
+            defaults.merge(options).unwrap(),
+            Any::Map(vec![
+                (key("a"), Any::Null),
+                (key("b"), Any::Long(2)),
+                (key("c"), Any::Long(3)),
+            ])
+        );
+        assert_eq!(
+            Any::Array(vec![Any::Long(1)])
+                .merge(Any::Array(vec![Any::Long(2)]))
+                .unwrap(),
+            Any::Array(vec![Any::Long(1), Any::Long(2)])
+        );
+        let error = Any::Map(vec![]).merge(Any::Array(vec![])).unwrap_err();
+        assert!(error.to_string().starts_with("Cannot merge map"));
+        assert!(Any::Long(1).merge(Any::Long(2)).is_err());
+    }
+
+    #[test]
+    fn test_packed() {
+        use crate::{Any, Packed};
+
+        let data = Any::packed(&[1i64, -2, i64::MAX]);
+        assert_eq!(data.as_buffer().unwrap().len(), 24);
+        assert_eq!(data.unpacked::<i64>(), Some(vec![1, -2, i64::MAX]));
+        assert_eq!(data.unpacked::<u32>().map(|x| x.len()), Some(6));
+
+        let data = Any::packed(&[0.5f64, 1.5]);
+        assert_eq!(data.unpacked::<f64>(), Some(vec![0.5, 1.5]));
+        assert_eq!(Any::Buffer(vec![0; 3]).unpacked::<i16>(), None);
+        assert_eq!(Any::Long(1).unpacked::<i64>(), None);
+        assert_eq!(Any::packed::<i32>(&[]), Any::Buffer(vec![]));
+        assert_eq!(<f64 as Packed>::FORMAT, "d");
+    }
+
+    #[test]
+    fn test_into_iter() {
+        use crate::Any;
+
+        let array: Any = vec![1, 2, 3].into();
+        let doubled: Any = array
+            .into_array_iter()
+            .map(|x| x.as_i64().unwrap() * 2)
+            .map(Any::from)
+            .collect();
+        assert_eq!(
+            doubled,
+            Any::Array(vec![Any::Long(2), Any::Long(4), Any::Long(6)])
+        );
+
+        let map = Any::Map(vec![(Any::Str("a".to_string()), Any::Int(1))]);
+        let entries: Vec<(Any, Any)> = map.clone().into_map_iter().collect();
+        assert_eq!(entries, vec![(Any::Str("a".to_string()), Any::Int(1))]);
+        assert_eq!(map.into_array_iter().count(), 0);
+        assert_eq!(Any::Null.into_map_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_initialize_error_reason() {
+        use std::ffi::OsString;
+
+        let reason = crate::initialize_error_reason(|_| None);
+        assert!(reason.contains("try setting SERIAL_LIBRARY_PATH"));
+
+        let reason = crate::initialize_error_reason(|var| match var {
+            "SERIAL_LIBRARY_PATH" => Some(OsString::from("/does/not/exist")),
+            _ => None,
+        });
+        assert_eq!(
+            reason,
+            "these paths do not exist: SERIAL_LIBRARY_PATH=\"/does/not/exist\""
+        );
+
+        let reason = crate::initialize_error_reason(|_| Some(OsString::from(".")));
+        assert_eq!(reason, "see the output of MetaCall for details");
+    }
+
+    #[test]
+    fn test_find_loader_library() {
+        use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+
+        let dir = std::env::temp_dir().join(format!("metacall-loaders-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let plugin = dir.join(format!("{}py_loaderd{}", DLL_PREFIX, DLL_SUFFIX));
+        std::fs::write(&plugin, b"").unwrap();
+
+        assert_eq!(crate::find_loader_library("py", &dir), Some(plugin));
+        assert_eq!(crate::find_loader_library("node", &dir), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_registry() {
+        use crate::{registry_get, registry_insert, Any};
+        use std::sync::Arc;
+
+        // The registry is cleared when MetaCall is destroyed by other tests
+        #[cfg(feature = "ffi-tests")]
+        let _lock = metacall_lock();
+
+        // Registrations from several threads get different ids
+        let ids: Vec<usize> = (0..8)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    let name = format!("test_registry_{}", i);
+                    registry_insert(&name, Arc::new(move |_: Vec<Any>| Ok(Any::Int(i)))).0
+                })
+            })
+            .map(|t| t.join().unwrap())
+            .collect();
+        for (i, id) in ids.iter().enumerate() {
+            let f = registry_get(*id).unwrap();
+            assert_eq!(f(vec![]).unwrap(), Any::Int(i as i32));
+        }
+        assert!(registry_get(0).is_none());
+
+        // A registered function can look up another one while it runs
+        let inner = ids[0];
+        let (outer, _) = registry_insert(
+            "test_registry_outer",
+            Arc::new(move |args: Vec<Any>| registry_get(inner).unwrap()(args)),
+        );
+        assert_eq!(registry_get(outer).unwrap()(vec![]).unwrap(), Any::Int(0));
+
+        // Registering a name again replaces (and releases) the previous function
+        let (id, replaced) =
+            registry_insert("test_registry_0", Arc::new(|_: Vec<Any>| Ok(Any::Int(-1))));
+        assert_eq!(replaced.unwrap()(vec![]).unwrap(), Any::Int(0));
+        assert!(registry_get(ids[0]).is_none());
+        assert_eq!(registry_get(id).unwrap()(vec![]).unwrap(), Any::Int(-1));
+    }
+
+    #[test]
+    fn test_value_id() {
+        use crate::{Any, MetacallValueId};
+
+        assert_eq!(Any::Null.value_id(), MetacallValueId::Null);
+        assert_eq!(Any::Long(1).value_id(), MetacallValueId::Long);
+        assert_eq!(Any::Float(1.0).value_id(), MetacallValueId::Float);
+        assert_eq!(Any::Char('a').value_id(), MetacallValueId::Char);
+        assert_eq!(Any::Char('é').value_id(), MetacallValueId::String);
+        assert_eq!(Any::from("a").value_id(), MetacallValueId::String);
+        assert_eq!(Any::Buffer(vec![]).value_id(), MetacallValueId::Buffer);
+        assert_eq!(Any::Map(vec![]).value_id(), MetacallValueId::Map);
+    }
+
+    #[test]
+    fn test_to_cstr_in() {
+        let mut buffer = Vec::with_capacity(16);
+        let ptr = buffer.as_ptr();
+        for value in ["a", "bc", "def"] {
+            let st = crate::to_cstr_in(value, "string", &mut buffer).unwrap();
+            assert_eq!(st.to_str().unwrap(), value);
+        }
+        // Strings shorter than the capacity reuse the same allocation
+        assert_eq!(buffer.as_ptr(), ptr);
+        assert!(crate::to_cstr_in("a\0b", "string", &mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_predicates() {
+        use crate::Any;
+
+        assert!(Any::Null.is_null());
+        assert!(!Any::Int(0).is_null());
+        assert!(Any::Short(1).is_number() && Any::Double(1.0).is_number());
+        assert!(!Any::Str("1".to_string()).is_number());
+        assert!(Any::Str("1".to_string()).is_str());
+        assert!(Any::Array(vec![]).is_array() && !Any::Array(vec![]).is_map());
+    }
+
+    #[test]
+    fn test_type_name() {
+        use crate::{Any, MetacallValueId};
+        use std::convert::TryFrom;
+
+        assert_eq!(Any::Null.type_name(), "null");
+        assert_eq!(Any::Map(vec![]).type_name(), "map");
+        assert_eq!(Any::Char('é').type_name(), "char");
+        assert_eq!(Any::Str(String::new()).type_name(), "string");
+        assert_eq!(MetacallValueId::Ptr.name(), "pointer");
+
+        let error = i32::try_from(Any::Str("a".to_string())).unwrap_err();
+        assert!(error.to_string().starts_with("Cannot convert string"));
+        assert_eq!(error.kind(), crate::ErrorKind::ConversionFailed);
+    }
+
+    #[test]
+    fn test_display() {
+        use crate::Any;
+
+        let value = Any::Map(vec![
+            (
+                Any::Str("a".to_string()),
+                Any::Array(vec![Any::Int(1), Any::Double(2.5)]),
+            ),
+            (Any::Str("b".to_string()), Any::Null),
+        ]);
+
+        assert_eq!(value.to_string(), "{a: [1, 2.5], b: null}");
+    }
+
+    #[test]
+    fn test_numeric_coercion() {
+        use crate::Any;
+
+        assert_eq!(Any::Short(23).as_i64(), Some(23));
+        assert_eq!(Any::Int(23).as_i64(), Some(23));
+        assert_eq!(Any::Long(23).as_i64(), Some(23));
+        assert_eq!(Any::Double(2.5).as_i64(), None);
+        assert_eq!(Any::Int(2).as_f64(), Some(2.0));
+        assert_eq!(Any::Float(2.5).as_f64(), Some(2.5));
+        assert_eq!(Any::Str("2".to_string()).as_f64(), None);
+    }
+
+    #[test]
+    fn test_equality() {
+        use crate::Any;
+
+        assert_eq!(
+            Any::Array(vec![Any::Str("a".to_string()), Any::Long(23)]),
+            Any::Array(vec![Any::Str("a".to_string()), Any::Long(23)])
+        );
+        assert_ne!(Any::Int(1), Any::Long(1));
+        assert_ne!(Any::Double(f64::NAN), Any::Double(f64::NAN));
+    }
+
+    #[test]
+    fn test_clone() {
+        use crate::Any;
+
+        fn identity(x: Any) -> Any {
+            x
+        }
+
+        let args = vec![
+            Any::Map(vec![(Any::Str("a".to_string()), Any::Buffer(vec![1, 2]))]),
+            Any::Function(Box::new(identity)),
+        ];
+
+        assert_eq!(args.clone(), args);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use crate::Any;
+
+        let value = Any::Map(vec![
+            (
+                Any::Str("a".to_string()),
+                Any::Array(vec![Any::Long(1), Any::Double(2.5), Any::Null]),
+            ),
+            (Any::Str("b".to_string()), Any::Buffer(vec![0, 1, 2])),
+        ]);
+
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(json, r#"{"a":[1,2.5,null],"b":"AAEC"}"#);
+
+        let value: Any = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            Any::Map(vec![
+                (
+                    Any::Str("a".to_string()),
+                    Any::Array(vec![Any::Long(1), Any::Double(2.5), Any::Null]),
+                ),
+                (Any::Str("b".to_string()), Any::Str("AAEC".to_string())),
+            ])
+        );
+
+        // Buffers do not round trip, they come back as their base64 string
+        let json = serde_json::to_string(&Any::Buffer(b"bytes".to_vec())).unwrap();
+        let value: Any = serde_json::from_str(&json).unwrap();
+        assert_eq!(value, Any::Str("Ynl0ZXM=".to_string()));
+        assert_eq!(base64::decode("Ynl0ZXM=").unwrap(), b"bytes".to_vec());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_value() {
+        use crate::Any;
+        use std::convert::TryFrom;
+
+        let json = serde_json::json!({ "a": [1, 2.5, null], "b": true });
+        let value = Any::from(json.clone());
+        assert_eq!(
+            value,
+            Any::Map(vec![
+                (
+                    Any::Str("a".to_string()),
+                    Any::Array(vec![Any::Long(1), Any::Double(2.5), Any::Null]),
+                ),
+                (Any::Str("b".to_string()), Any::Bool(true)),
+            ])
+        );
+        assert_eq!(serde_json::Value::try_from(value).unwrap(), json);
+
+        fn identity(value: Any) -> Any {
+            value
+        }
+        assert!(serde_json::Value::try_from(Any::Function(Box::new(identity))).is_err());
+    }
+
+    #[test]
+    fn test_parse_inspection() {
+        use crate::{Any, MetacallValueId};
+
+        fn s(value: &str) -> Any {
+            Any::Str(value.to_string())
+        }
+
+        let type_id = |id| Any::Map(vec![(s("type"), Any::Map(vec![(s("id"), Any::Int(id))]))]);
+
+        let func = Any::Map(vec![
+            (s("name"), s("sum")),
+            (
+                s("signature"),
+                Any::Map(vec![
+                    (s("ret"), type_id(4)),
+                    (
+                        s("args"),
+                        Any::Array(vec![Any::Map(vec![
+                            (s("name"), s("a")),
+                            (s("type"), Any::Map(vec![(s("id"), Any::Int(18))])),
+                        ])]),
+                    ),
+                ]),
+            ),
+            (s("async"), Any::Bool(false)),
+        ]);
+
+        let value = Any::Map(vec![(
+            s("py"),
+            Any::Array(vec![Any::Map(vec![
+                (s("name"), s("script.py")),
+                (
+                    s("scope"),
+                    Any::Map(vec![(s("funcs"), Any::Array(vec![func]))]),
+                ),
+            ])]),
+        )]);
+
+        let inspection = crate::inspect::parse_inspection(&value).unwrap();
+
+        assert_eq!(inspection.modules.len(), 1);
+        assert_eq!(inspection.modules[0].tag, "py");
+        assert_eq!(inspection.modules[0].name, "script.py");
+
+        let func = inspection.functions().next().unwrap();
+        assert_eq!(func.name, "sum");
+        assert_eq!(func.ret, Some(MetacallValueId::Long));
+        assert_eq!(func.args[0].name, "a");
+        assert_eq!(func.args[0].type_id, None);
+        assert!(!func.is_async);
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_initialize_reference_count() {
+        let _lock = metacall_lock();
+        let outer = crate::initialize().unwrap();
+        let inner = crate::initialize().unwrap();
+
+        drop(inner);
+        assert!(crate::is_initialized());
+
+        drop(outer);
+        assert!(!crate::is_initialized());
+
+        // Destroying when it is not initialized must be a no-op
+        crate::destroy();
+        assert!(!crate::is_initialized());
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_map_round_trip() {
+        use crate::Any;
+
+        let map = Any::Map(vec![(
+            Any::Str("list".to_string()),
+            Any::Array(vec![Any::Int(1), Any::Array(vec![Any::Int(2)])]),
+        )]);
+
+        let value = unsafe { crate::any_to_metacall(&map) }.unwrap();
+        let result = unsafe { crate::metacall_to_any(value) };
+        unsafe { crate::metacall_value_destroy(value) };
+
+        match result {
+            Any::Map(entries) => match entries.as_slice() {
+                [(Any::Str(key), Any::Array(list))] => {
+                    assert_eq!(key, "list");
+                    assert!(
+                        matches!(list.as_slice(), [Any::Int(1), Any::Array(inner)] if matches!(inner.as_slice(), [Any::Int(2)]))
+                    );
+                }
+                _ => panic!("unexpected map entries: {:?}", entries),
+            },
+            _ => panic!("expected a map, got {:?}", result),
+        }
+
+        // Same as a dictionary returned by Python, i.e {"a": 1, "b": [2, 3]}
+        let dict = Any::Map(vec![
+            (Any::Str("a".to_string()), Any::Long(1)),
+            (
+                Any::Str("b".to_string()),
+                Any::Array(vec![Any::Long(2), Any::Long(3)]),
+            ),
+        ]);
+        let value = unsafe { crate::any_to_metacall(&dict) }.unwrap();
+        assert_eq!(unsafe { crate::metacall_to_any(value) }, dict);
+        unsafe { crate::metacall_value_destroy(value) };
+
+        // Sparse maps (with empty or incomplete entries) keep only their complete entries
+        unsafe {
+            let tuples = [
+                std::ptr::null_mut(),
+                crate::any_to_metacall(&Any::Array(vec![Any::Str("a".to_string()), Any::Int(1)]))
+                    .unwrap(),
+                crate::any_to_metacall(&Any::Array(vec![Any::Str("b".to_string())])).unwrap(),
+            ];
+            let value = crate::metacall_value_create_map(
+                tuples.as_ptr() as *const *const std::os::raw::c_void,
+                tuples.len(),
+            );
+            assert_eq!(
+                crate::metacall_to_any(value),
+                Any::Map(vec![(Any::Str("a".to_string()), Any::Int(1))])
+            );
+            crate::metacall_value_destroy(value);
+        }
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_buffer_round_trip() {
+        use crate::Any;
+
+        let buffer = Any::Buffer(vec![0x01, 0x00, 0xff, 0x00]);
+
+        let value = unsafe { crate::any_to_metacall(&buffer) }.unwrap();
+        let result = unsafe { crate::metacall_to_any(value) };
+        unsafe { crate::metacall_value_destroy(value) };
+
+        match result {
+            Any::Buffer(bytes) => assert_eq!(bytes, vec![0x01, 0x00, 0xff, 0x00]),
+            _ => panic!("expected a buffer, got {:?}", result),
+        }
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_pointer_round_trip() {
+        use crate::{Any, OpaquePointer};
+
+        let mut data: i32 = 15;
+        let ptr = unsafe { OpaquePointer::new(&mut data as *mut i32 as *mut _) };
+
+        let value = unsafe { crate::any_to_metacall(&Any::Pointer(ptr)) }.unwrap();
+        let result = unsafe { crate::metacall_to_any(value) };
+        unsafe { crate::metacall_value_destroy(value) };
+
+        assert_eq!(result, Any::Pointer(ptr));
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_pending_future() {
+        use crate::{Any, AwaitState, PendingFuture};
+        use std::sync::{Arc, Mutex};
+
+        let state = Arc::new(Mutex::new(AwaitState {
+            result: None,
+            waker: None,
+        }));
+        let future = PendingFuture(state.clone());
+        assert!(future.try_resolve().is_none());
+
+        let data = Arc::into_raw(state) as *mut std::os::raw::c_void;
+        unsafe { crate::await_settle(data, Ok(Any::Int(1))) };
+        assert_eq!(future.try_resolve().unwrap().unwrap(), Any::Int(1));
+        assert!(future.try_resolve().is_none());
+
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+        assert!(crate::metacall_future("does_not_exist", &[]).is_err());
+        // Functions which are not async are never settled, so they fail before being awaited
+        assert!(!crate::function("my_empty_func").unwrap().is_async());
+        match crate::metacall_future("my_empty_func", &[]) {
+            Err(e) => assert_eq!(e.kind(), crate::ErrorKind::CallFailed),
+            Ok(_) => panic!("my_empty_func should not be awaitable"),
+        }
+    }
+
+    #[cfg(all(feature = "tokio", feature = "ffi-tests"))]
+    #[test]
+    fn test_await_tokio_not_found() {
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        assert!(crate::metacall_await_tokio("does_not_exist", &[]).is_err());
+        // The sender is dropped instead of waiting for callbacks that are never called
+        match crate::metacall_await_tokio("my_empty_func", &[]) {
+            Err(e) => assert_eq!(e.kind(), crate::ErrorKind::CallFailed),
+            Ok(_) => panic!("my_empty_func is not async, it should not be awaitable"),
+        }
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_metacall() {
+        let _lock = metacall_lock();
+        let _guard = match crate::initialize() {
+            Err(e) => {
+                println!("{}", e);
+                panic!();
+            }
+            Ok(guard) => {
+                println!(" Hello World Metacall created ");
+                guard
+            }
+        };
+
+        let scripts = ["test.mock"];
+
+        if let Err(e) = crate::load_from_file("mock", scripts) {
+            println!("{}", e);
+            panic!();
+        }
+
+        match crate::metacall("new_args", &[crate::Any::Str("a".to_string())]) {
+            Ok(ret) => match ret {
+                crate::Any::Str(value) => {
+                    assert_eq!("Hello World".to_string(), value);
+                    println!("Result: {}", value);
+                }
+                _ => {
+                    assert_eq!(0, 1);
+                    panic!();
+                }
+            },
+            Err(e) => {
                 println!("{}", e);
                 assert_eq!(0, 1);
                 panic!();
             }
         }
+
+        assert!(crate::metacall("new_args", &[crate::Any::Null]).is_ok());
+        assert!(crate::metacall("new_args", vec![crate::Any::Str("a".to_string())]).is_ok());
+        assert_eq!(
+            crate::metacall("does_not_exist", &[]).unwrap_err().kind(),
+            crate::ErrorKind::FunctionNotFound
+        );
+    }
+
+    /// Register rust_echo, which returns its argument, for checking the values received by a
+    /// function called through MetaCall (its types are dynamic, so MetaCall does not cast them)
+    #[cfg(feature = "ffi-tests")]
+    fn register_echo() {
+        use crate::MetacallValueId;
+
+        crate::register(
+            "rust_echo",
+            |mut args| args.swap_remove(0),
+            &[MetacallValueId::Invalid],
+            MetacallValueId::Invalid,
+        )
+        .unwrap();
+    }
+
+    /// Initialize MetaCall and load the functions of the mock loader into the global scope
+    /// (new_args, two_str, two_doubles, my_empty_func), they are unloaded with the guard
+    #[cfg(feature = "ffi-tests")]
+    fn initialize_mock() -> crate::MetacallGuard {
+        let guard = crate::initialize().unwrap();
+        crate::load_from_file("mock", ["test.mock"]).unwrap();
+        guard
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_metacall_in() {
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        assert_eq!(
+            crate::metacall_in("mock", "test.mock", "new_args", &["a".into()]).unwrap(),
+            crate::Any::Str("Hello World".to_string())
+        );
+        assert!(crate::metacall_in("mock", "other.mock", "new_args", &[]).is_err());
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_function_param_names() {
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        assert_eq!(crate::function_param_names("new_args").unwrap(), ["a_str"]);
+        assert!(crate::function_param_names("does_not_exist").is_err());
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_metacall_kw() {
+        use crate::Any;
+        use std::collections::HashMap;
+
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        let mut kwargs = HashMap::new();
+        kwargs.insert("b_str".to_string(), Any::from("b"));
+        assert_eq!(
+            crate::metacall_kw("two_str", &["a".into()], kwargs.clone()).unwrap(),
+            Any::Str("Hello World".to_string())
+        );
+        assert!(crate::metacall_kw("two_str", &[], kwargs).is_err());
+
+        let mut kwargs = HashMap::new();
+        kwargs.insert("c_str".to_string(), Any::from("c"));
+        assert!(crate::metacall_kw("two_str", &["a".into()], kwargs).is_err());
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_call_or_load() {
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        // The script is loaded by the first call and reused by the second one
+        for _ in 0..2 {
+            assert_eq!(
+                crate::call_or_load("mock", "test.mock", "new_args", &["a".into()]).unwrap(),
+                crate::Any::Str("Hello World".to_string())
+            );
+        }
+        assert!(crate::call_or_load("mock", "lazy.mock", "does_not_exist", &[]).is_err());
+        // Invalid names fail without loading the script
+        let error = crate::call_or_load("mock", "invalid.mock", "a\0b", &[]).unwrap_err();
+        assert_eq!(error.kind(), crate::ErrorKind::ConversionFailed);
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_loader_available() {
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        assert!(crate::loader_available("mock"));
+        assert!(!crate::loader_available("none"));
+        assert!(crate::loader_initialized("mock"));
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_metacall_strict() {
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        assert!(crate::metacall_strict("new_args", &["a".into()]).is_ok());
+        assert!(crate::metacall_strict("new_args", &[]).is_err());
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_casted_arguments() {
+        use crate::Any;
+
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        // The ints are casted into doubles by MetaCall, replacing the arguments, which
+        // must be destroyed only once (run the tests with valgrind to check it)
+        for _ in 0..100 {
+            let ret = crate::metacall("two_doubles", &[Any::Int(1), Any::Int(2)]);
+            assert!(ret.unwrap().is_number());
+        }
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_metacall_raw() {
+        use crate::raw::Value;
+
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        let mut args = [Value::string("a").unwrap()];
+        let ret = crate::raw::metacall_raw("new_args", &mut args).unwrap();
+        assert_eq!(ret.as_str(), Some("Hello World"));
+        assert_eq!(ret.to_int(), None);
+
+        // The int is copied and cast into a double for each call, the original is kept
+        let first = Value::double(1.0);
+        let second = Value::int(2);
+        for _ in 0..2 {
+            let ret = crate::raw::metacall_raw_ref("two_doubles", &[&first, &second]).unwrap();
+            assert!(ret.to_double().is_some());
+        }
+        assert_eq!(second.to_int(), Some(2));
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_metacall_map() {
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        assert_eq!(
+            crate::metacall_map("new_args", [["a".into()], ["b".into()]]).unwrap(),
+            [
+                crate::Any::Str("Hello World".to_string()),
+                crate::Any::Str("Hello World".to_string())
+            ]
+        );
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_metacall_with_timeout() {
+        use crate::{Any, MetacallValueId};
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let _lock = metacall_lock();
+        let guard = initialize_mock();
+
+        assert_eq!(
+            crate::metacall_with_timeout("my_empty_func", &[], Duration::from_secs(10)).unwrap(),
+            Any::Int(1234)
+        );
+
+        // After a timeout the worker keeps running, destroying MetaCall waits for it
+        let finished = Arc::new(AtomicBool::new(false));
+        let flag = finished.clone();
+        crate::register(
+            "rust_sleep",
+            move |_| {
+                std::thread::sleep(Duration::from_millis(200));
+                flag.store(true, Ordering::SeqCst);
+                Any::Null
+            },
+            &[],
+            MetacallValueId::Null,
+        )
+        .unwrap();
+        assert!(crate::metacall_with_timeout("rust_sleep", &[], Duration::from_millis(1)).is_err());
+        drop(guard);
+        assert!(finished.load(Ordering::SeqCst));
+        assert!(crate::metacall_with_timeout("rust_sleep", &[], Duration::from_secs(1)).is_err());
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_handle() {
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        let handle = crate::load_from_file_handle("mock", ["clear.mock"]).unwrap();
+        assert!(crate::metacall_handle(&handle, "new_args", &["a".into()]).is_ok());
+        let functions = crate::handle_functions(&handle).unwrap();
+        assert!(functions.iter().any(|f| f == "new_args"));
+        crate::clear(handle).unwrap();
+        assert!(crate::metacall_in("mock", "clear.mock", "new_args", &["a".into()]).is_err());
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_metacall_struct() {
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        assert!(crate::metacall_struct("two_str", ("a", "b")).is_ok());
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_eval() {
+        let _lock = metacall_lock();
+        let _guard = crate::initialize().unwrap();
+
+        // The source is unloaded after each evaluation, so it can define the same functions
+        for _ in 0..2 {
+            assert_eq!(
+                crate::eval("mock", "snippet", "new_args", &["a".into()]).unwrap(),
+                crate::Any::Str("Hello World".to_string())
+            );
+        }
+        assert!(crate::eval("mock", "snippet", "does_not_exist", &[]).is_err());
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_metacall_macro() {
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        match crate::metacall!("new_args", "a") {
+            Ok(crate::Any::Str(value)) => assert_eq!("Hello World".to_string(), value),
+            Ok(ret) => panic!("unexpected result: {:?}", ret),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[cfg(feature = "ffi-tests")]
+    #[test]
+    fn test_metacall0() {
+        let _lock = metacall_lock();
+        let _guard = initialize_mock();
+
+        for _ in 0..1000 {
+            assert_eq!(
+                crate::metacall0("my_empty_func").unwrap(),
+                crate::Any::Int(1234)
+            );
+        }
     }
 }
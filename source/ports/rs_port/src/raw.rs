@@ -0,0 +1,288 @@
+/*
+ *	MetaCall Library by Parra Studios
+ *	A library for providing a foreign function interface calls.
+ *
+ *	Copyright (C) 2016 - 2021 Vicente Eduardo Ferrer Garcia <vic798@gmail.com>
+ *
+ *	Licensed under the Apache License, Version 2.0 (the "License");
+ *	you may not use this file except in compliance with the License.
+ *	You may obtain a copy of the License at
+ *
+ *		http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *	Unless required by applicable law or agreed to in writing, software
+ *	distributed under the License is distributed on an "AS IS" BASIS,
+ *	WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *	See the License for the specific language governing permissions and
+ *	limitations under the License.
+ *
+ */
+
+//! Advanced access to MetaCall values without converting them into Any, so they can be
+//! reused or duplicated directly in C; most users should not need anything from here
+
+use crate::{
+    any_to_metacall, call_failed_error, entry, function, metacall_to_any, metacall_value_copy,
+    metacall_value_create_bool, metacall_value_create_buffer, metacall_value_create_char,
+    metacall_value_create_double, metacall_value_create_float, metacall_value_create_int,
+    metacall_value_create_long, metacall_value_create_short, metacall_value_create_string,
+    metacall_value_destroy, metacall_value_id, metacall_value_size, metacall_value_to_bool,
+    metacall_value_to_buffer, metacall_value_to_char, metacall_value_to_double,
+    metacall_value_to_float, metacall_value_to_int, metacall_value_to_long,
+    metacall_value_to_short, metacall_value_to_string, metacallfv_s, to_cstring, with_args_array,
+    Any, Error, ErrorKind, MetacallValueId,
+};
+use std::os::raw::{c_char, c_uchar, c_void};
+
+/// Owned MetaCall value, it is destroyed when dropped, so the pointer returned by as_ptr must
+/// not be destroyed by hand (into_raw releases the ownership if it must be destroyed in C)
+///
+/// A value can be borrowed by several calls (see metacall_raw_ref), so big values (i.e buffers)
+/// are converted only once instead of on each call
+#[derive(Debug)]
+pub struct Value(*mut c_void);
+
+impl Value {
+    /// Create a MetaCall value from an Any
+    pub fn from_any(value: &Any) -> Result<Value, Error> {
+        entry(|| Ok(Value(unsafe { any_to_metacall(value)? })))
+    }
+
+    /// Create a short value
+    pub fn short(value: i16) -> Value {
+        Value(unsafe { metacall_value_create_short(value) })
+    }
+
+    /// Create an int value
+    pub fn int(value: i32) -> Value {
+        Value(unsafe { metacall_value_create_int(value) })
+    }
+
+    /// Create a long value
+    pub fn long(value: i64) -> Value {
+        Value(unsafe { metacall_value_create_long(value) })
+    }
+
+    /// Create a float value
+    pub fn float(value: f32) -> Value {
+        Value(unsafe { metacall_value_create_float(value) })
+    }
+
+    /// Create a double value
+    pub fn double(value: f64) -> Value {
+        Value(unsafe { metacall_value_create_double(value) })
+    }
+
+    /// Create a bool value
+    pub fn bool(value: bool) -> Value {
+        Value(unsafe { metacall_value_create_bool(value as c_uchar) })
+    }
+
+    /// Create a char value, MetaCall chars are a single byte
+    pub fn char(value: c_char) -> Value {
+        Value(unsafe { metacall_value_create_char(value) })
+    }
+
+    /// Create a string value, it fails if the string contains a null byte
+    pub fn string(value: &str) -> Result<Value, Error> {
+        entry(|| {
+            let st = to_cstring(value, "string")?;
+            // SAFETY: st lives until the end of the function and MetaCall copies it
+            Ok(Value(unsafe {
+                metacall_value_create_string(st.as_ptr(), value.len())
+            }))
+        })
+    }
+
+    /// Create a buffer value, the data is copied into it
+    pub fn buffer(value: &[u8]) -> Value {
+        Value(unsafe { metacall_value_create_buffer(value.as_ptr() as *const c_void, value.len()) })
+    }
+
+    /// Take ownership of a MetaCall value, it will be destroyed with the Value
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be a valid MetaCall value not owned by anything else
+    pub unsafe fn from_raw(value: *mut c_void) -> Value {
+        Value(value)
+    }
+
+    /// Release the ownership of the MetaCall value, so it must be destroyed by the caller
+    pub fn into_raw(self) -> *mut c_void {
+        let value = self.0;
+        std::mem::forget(self);
+        value
+    }
+
+    /// Pointer to the MetaCall value, it is still owned by the Value
+    pub fn as_ptr(&self) -> *mut c_void {
+        self.0
+    }
+
+    /// Type of the value, None if MetaCall returns an unknown id
+    pub fn id(&self) -> Option<MetacallValueId> {
+        MetacallValueId::from_raw(unsafe { metacall_value_id(self.0) })
+    }
+
+    /// Value of a short, None if the value is of other type
+    pub fn to_short(&self) -> Option<i16> {
+        self.is(MetacallValueId::Short)
+            .then(|| unsafe { metacall_value_to_short(self.0) })
+    }
+
+    /// Value of an int, None if the value is of other type
+    pub fn to_int(&self) -> Option<i32> {
+        self.is(MetacallValueId::Int)
+            .then(|| unsafe { metacall_value_to_int(self.0) })
+    }
+
+    /// Value of a long, None if the value is of other type
+    pub fn to_long(&self) -> Option<i64> {
+        self.is(MetacallValueId::Long)
+            .then(|| unsafe { metacall_value_to_long(self.0) })
+    }
+
+    /// Value of a float, None if the value is of other type
+    pub fn to_float(&self) -> Option<f32> {
+        self.is(MetacallValueId::Float)
+            .then(|| unsafe { metacall_value_to_float(self.0) })
+    }
+
+    /// Value of a double, None if the value is of other type
+    pub fn to_double(&self) -> Option<f64> {
+        self.is(MetacallValueId::Double)
+            .then(|| unsafe { metacall_value_to_double(self.0) })
+    }
+
+    /// Value of a bool, None if the value is of other type
+    pub fn to_bool(&self) -> Option<bool> {
+        self.is(MetacallValueId::Bool)
+            .then(|| unsafe { metacall_value_to_bool(self.0) } != 0)
+    }
+
+    /// Value of a char, None if the value is of other type
+    pub fn to_char(&self) -> Option<c_char> {
+        self.is(MetacallValueId::Char)
+            .then(|| unsafe { metacall_value_to_char(self.0) })
+    }
+
+    /// Contents of a string without copying them, None if the value
+    /// is of other type or the string is not valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        if !self.is(MetacallValueId::String) {
+            return None;
+        }
+        // The size of MetaCall strings includes the null terminator
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                metacall_value_to_string(self.0) as *const u8,
+                metacall_value_size(self.0).saturating_sub(1),
+            )
+        };
+        std::str::from_utf8(bytes).ok()
+    }
+
+    /// Contents of a buffer without copying them, None if the value is of other type
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        if !self.is(MetacallValueId::Buffer) {
+            return None;
+        }
+        let size = unsafe { metacall_value_size(self.0) };
+        if size == 0 {
+            return Some(&[]);
+        }
+        Some(unsafe {
+            std::slice::from_raw_parts(metacall_value_to_buffer(self.0) as *const u8, size)
+        })
+    }
+
+    fn is(&self, id: MetacallValueId) -> bool {
+        self.id() == Some(id)
+    }
+
+    /// Convert the value into an Any, copying its contents
+    pub fn to_any(&self) -> Any {
+        unsafe { metacall_to_any(self.0) }
+    }
+
+    /// Human readable name of the type of the value, without converting it into an Any
+    pub fn type_name(&self) -> &'static str {
+        self.id().map_or("invalid", MetacallValueId::name)
+    }
+
+    /// Duplicate the value (with metacall_value_copy), compound values like arrays
+    /// or maps are copied deeply, so both values can be destroyed independently
+    pub fn copy(&self) -> Result<Value, Error> {
+        entry(|| {
+            let value = unsafe { metacall_value_copy(self.0) };
+            if value.is_null() {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    "MetaCall failed to copy the value",
+                ));
+            }
+            Ok(Value(value))
+        })
+    }
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        unsafe { metacall_value_destroy(self.0) };
+    }
+}
+
+/// Call a function by name with MetaCall values as arguments, without converting them
+/// from or into Any; MetaCall may convert the arguments in place into the types declared
+/// by the function, so they are taken mutably and may change their type after the call
+pub fn metacall_raw(func: &str, args: &mut [Value]) -> Result<Value, Error> {
+    entry(|| {
+        let function = function(func)?;
+        let mut c_args: Vec<*mut c_void> = args.iter().map(Value::as_ptr).collect();
+        let ret = with_args_array(&mut c_args, |c_args, size| unsafe {
+            metacallfv_s(function.0, c_args, size)
+        });
+        // The arguments that were converted have been replaced (and the old ones destroyed)
+        for (arg, c_arg) in args.iter_mut().zip(c_args) {
+            arg.0 = c_arg;
+        }
+        if ret.is_null() {
+            return Err(call_failed_error(func));
+        }
+        Ok(Value(ret))
+    })
+}
+
+/// Same as metacall_raw but borrowing the arguments, so the same value can be passed to several
+/// calls and it is still destroyed only once (when dropped); the arguments whose type differs
+/// from the declared by the function are copied for the call, so they are never cast in place
+pub fn metacall_raw_ref(func: &str, args: &[&Value]) -> Result<Value, Error> {
+    entry(|| {
+        let function = function(func)?;
+        let types = function.arg_types();
+        let mut copies: Vec<(usize, Value)> = Vec::new();
+        let mut c_args: Vec<*mut c_void> = Vec::with_capacity(args.len() + 1);
+        for (i, arg) in args.iter().enumerate() {
+            match types.get(i) {
+                Some(Some(id)) if Some(*id) != arg.id() => {
+                    let copy = arg.copy()?;
+                    c_args.push(copy.0);
+                    copies.push((i, copy));
+                }
+                _ => c_args.push(arg.0),
+            }
+        }
+        // Only the copies (owned by this function) can be replaced by MetaCall
+        let ret = with_args_array(&mut c_args, |c_args, size| unsafe {
+            metacallfv_s(function.0, c_args, size)
+        });
+        for (i, copy) in copies.iter_mut() {
+            copy.0 = c_args[*i];
+        }
+        if ret.is_null() {
+            return Err(call_failed_error(func));
+        }
+        Ok(Value(ret))
+    })
+}
@@ -0,0 +1,195 @@
+/*
+ *	MetaCall Library by Parra Studios
+ *	A library for providing a foreign function interface calls.
+ *
+ *	Copyright (C) 2016 - 2021 Vicente Eduardo Ferrer Garcia <vic798@gmail.com>
+ *
+ *	Licensed under the Apache License, Version 2.0 (the "License");
+ *	you may not use this file except in compliance with the License.
+ *	You may obtain a copy of the License at
+ *
+ *		http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *	Unless required by applicable law or agreed to in writing, software
+ *	distributed under the License is distributed on an "AS IS" BASIS,
+ *	WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *	See the License for the specific language governing permissions and
+ *	limitations under the License.
+ *
+ */
+
+//! Serde support for Any (enabled with the serde feature), values are mapped into their
+//! natural JSON types, buffers are serialized as base64 strings and pointers or functions
+//! cannot be serialized; deserialized integers are Any::Long and floats Any::Double
+
+use crate::Any;
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Error, Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::fmt;
+
+// Buffers are serialized as base64 strings, which are not tagged, so they cannot be told apart
+// from other strings when deserializing: a serialized Any::Buffer is deserialized as an Any::Str
+// holding the base64 text, decode it (i.e with the base64 crate) in order to get the bytes back
+impl Serialize for Any {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Any::Null => serializer.serialize_unit(),
+            Any::Short(x) => serializer.serialize_i16(*x),
+            Any::Int(x) => serializer.serialize_i32(*x),
+            Any::Long(x) => serializer.serialize_i64(*x),
+            Any::Float(x) => serializer.serialize_f32(*x),
+            Any::Double(x) => serializer.serialize_f64(*x),
+            Any::Bool(x) => serializer.serialize_bool(*x),
+            Any::Char(x) => serializer.serialize_char(*x),
+            Any::Str(x) => serializer.serialize_str(x),
+            Any::Array(x) => {
+                let mut seq = serializer.serialize_seq(Some(x.len()))?;
+                for v in x {
+                    seq.serialize_element(v)?;
+                }
+                seq.end()
+            }
+            Any::Map(x) => {
+                let mut map = serializer.serialize_map(Some(x.len()))?;
+                for (k, v) in x {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            }
+            Any::Buffer(x) => serializer.serialize_str(&base64::encode(x)),
+            Any::Pointer(_) => Err(S::Error::custom("Any::Pointer cannot be serialized")),
+            Any::Function(_) | Any::FunctionValue(_) => {
+                Err(S::Error::custom("Any::Function cannot be serialized"))
+            }
+        }
+    }
+}
+
+struct AnyVisitor;
+
+impl<'de> Visitor<'de> for AnyVisitor {
+    type Value = Any;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a null, boolean, number, string, array or map")
+    }
+
+    fn visit_unit<E>(self) -> Result<Any, E> {
+        Ok(Any::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Any, E> {
+        Ok(Any::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Any, D::Error> {
+        Any::deserialize(deserializer)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Any, E> {
+        Ok(Any::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Any, E> {
+        Ok(Any::Long(v))
+    }
+
+    // Integers bigger than i64::MAX do not fit into any integer variant
+    fn visit_u64<E>(self, v: u64) -> Result<Any, E> {
+        if v > i64::MAX as u64 {
+            Ok(Any::Double(v as f64))
+        } else {
+            Ok(Any::Long(v as i64))
+        }
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Any, E> {
+        Ok(Any::Double(v))
+    }
+
+    fn visit_char<E>(self, v: char) -> Result<Any, E> {
+        Ok(Any::Char(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Any, E> {
+        Ok(Any::Str(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Any, E> {
+        Ok(Any::Str(v))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Any, E> {
+        Ok(Any::Buffer(v.to_vec()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Any, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(v) = seq.next_element()? {
+            values.push(v);
+        }
+        Ok(Any::Array(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Any, A::Error> {
+        let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry);
+        }
+        Ok(Any::Map(entries))
+    }
+}
+
+// Strings are always Any::Str, including the base64 strings of serialized buffers (see Serialize),
+// only formats with a native bytes type (visit_bytes) are deserialized as Any::Buffer
+impl<'de> Deserialize<'de> for Any {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Any, D::Error> {
+        deserializer.deserialize_any(AnyVisitor)
+    }
+}
+
+// The mapping is the same as deserializing, so every JSON value converts into an Any, but
+// pointers, functions and maps with keys which are not strings or numbers cannot be converted
+// into JSON (it goes through Serialize, so buffers are base64 strings as in other formats)
+#[cfg(feature = "json")]
+impl From<serde_json::Value> for Any {
+    fn from(val: serde_json::Value) -> Self {
+        use serde_json::Value;
+
+        match val {
+            Value::Null => Any::Null,
+            Value::Bool(x) => Any::Bool(x),
+            Value::Number(x) => match x.as_i64() {
+                Some(x) => Any::Long(x),
+                // Floats and integers bigger than i64::MAX
+                None => Any::Double(x.as_f64().unwrap_or(f64::NAN)),
+            },
+            Value::String(x) => Any::Str(x),
+            Value::Array(x) => Any::Array(x.into_iter().map(Any::from).collect()),
+            Value::Object(x) => Any::Map(
+                x.into_iter()
+                    .map(|(k, v)| (Any::Str(k), Any::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl std::convert::TryFrom<Any> for serde_json::Value {
+    type Error = crate::Error;
+
+    fn try_from(val: Any) -> Result<Self, Self::Error> {
+        serde_json::to_value(&val).map_err(|e| {
+            crate::Error::new(
+                crate::ErrorKind::ConversionFailed,
+                format!(
+                    "Cannot convert {} {:?} into JSON: {}",
+                    val.type_name(),
+                    val,
+                    e
+                ),
+            )
+        })
+    }
+}
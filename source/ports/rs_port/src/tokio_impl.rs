@@ -0,0 +1,82 @@
+/*
+ *	MetaCall Library by Parra Studios
+ *	A library for providing a foreign function interface calls.
+ *
+ *	Copyright (C) 2016 - 2021 Vicente Eduardo Ferrer Garcia <vic798@gmail.com>
+ *
+ *	Licensed under the Apache License, Version 2.0 (the "License");
+ *	you may not use this file except in compliance with the License.
+ *	You may obtain a copy of the License at
+ *
+ *		http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *	Unless required by applicable law or agreed to in writing, software
+ *	distributed under the License is distributed on an "AS IS" BASIS,
+ *	WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *	See the License for the specific language governing permissions and
+ *	limitations under the License.
+ *
+ */
+
+//! Tokio support (enabled with the tokio feature), the call is started in the thread that
+//! owns MetaCall, and its result is sent through a oneshot channel when the future settles;
+//! the returned future is Send and 'static, so it can be awaited from any tokio task.
+//! Some loaders settle their futures from their own threads (i.e NodeJS resolves promises
+//! in its event loop thread), the channel makes the result safe to receive in any thread
+
+use crate::{await_call, await_result, entry, fail, Any, Error, ErrorKind};
+use std::borrow::Borrow;
+use std::future::Future;
+use std::os::raw::c_void;
+use tokio::sync::oneshot;
+
+type Sender = oneshot::Sender<Result<Any, Error>>;
+
+/// Send the result through the channel, only one of the callbacks
+/// is ever called, so it takes back the sender leaked by the call
+unsafe fn settle(data: *mut c_void, result: Result<Any, Error>) {
+    let sender = Box::from_raw(data as *mut Sender);
+    // The receiver may have been dropped if nobody is awaiting the result anymore
+    let _ = sender.send(result);
+}
+
+extern "C" fn resolve(value: *mut c_void, data: *mut c_void) -> *mut c_void {
+    // A panic must not unwind into MetaCall, see await_resolve
+    let _ = std::panic::catch_unwind(|| unsafe { settle(data, await_result(value, true)) });
+    std::ptr::null_mut()
+}
+
+extern "C" fn reject(value: *mut c_void, data: *mut c_void) -> *mut c_void {
+    let _ = std::panic::catch_unwind(|| unsafe { settle(data, await_result(value, false)) });
+    std::ptr::null_mut()
+}
+
+/// Call an asynchronous function and return a future (that can be spawned in tokio)
+/// resolved with its result, errors calling the function are returned immediately, as
+/// well as calls to functions which are not async (see metacall_future)
+pub fn metacall_await_tokio(
+    func: &str,
+    args: impl IntoIterator<Item = impl Borrow<Any>>,
+) -> Result<impl Future<Output = Result<Any, Error>> + Send + 'static, Error> {
+    entry(|| {
+        let (sender, receiver) = oneshot::channel();
+        let data = Box::into_raw(Box::new(sender)) as *mut c_void;
+        // await_call checks that the function is async before calling it, if it is not (or
+        // the call fails) none of the callbacks is ever called, so the sender is dropped here
+        if let Err(e) = unsafe { await_call(func, args, resolve, reject, data) } {
+            drop(unsafe { Box::from_raw(data as *mut Sender) });
+            return Err(e);
+        }
+        Ok(async move {
+            receiver
+                .await
+                .unwrap_or_else(|_| {
+                    Err(Error::new(
+                        ErrorKind::CallFailed,
+                        "Future was destroyed before being settled",
+                    ))
+                })
+                .map_err(fail)
+        })
+    })
+}